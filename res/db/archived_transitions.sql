@@ -0,0 +1,6 @@
+-- PREPARE archived_transitions (uuid[]) AS
+
+SELECT event_id, old_state, new_state, changed_at, changed_by
+FROM event_transitions
+WHERE event_id = ANY($1)
+ORDER BY id ASC;
@@ -0,0 +1,3 @@
+SELECT namespace, CAST(state AS TEXT) AS state, COUNT(*) AS count
+FROM events
+GROUP BY namespace, state;
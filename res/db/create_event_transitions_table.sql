@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS event_transitions(
+    id                     BIGSERIAL                       NOT NULL PRIMARY KEY,
+    event_id               UUID                            NOT NULL REFERENCES events(id) ON DELETE CASCADE,
+    old_state              state                           NOT NULL,
+    new_state              state                           NOT NULL,
+    changed_at             TIMESTAMP WITH TIME ZONE        NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    changed_by             VARCHAR(128)
+);
+
+CREATE INDEX IF NOT EXISTS event_transitions_idx ON event_transitions(event_id, id);
\ No newline at end of file
@@ -0,0 +1,27 @@
+CREATE OR REPLACE FUNCTION notify_event_change() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('event_changes', json_build_object(
+        'op', lower(TG_OP),
+        'id', NEW.id,
+        'namespace', NEW.namespace,
+        'key', NEW.key,
+        'oldState', CASE WHEN TG_OP = 'UPDATE' THEN OLD.state END,
+        'state', NEW.state,
+        'scheduledAt', NEW.scheduled_at,
+        'version', NEW.version
+    )::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS event_inserted ON events;
+CREATE TRIGGER event_inserted
+    AFTER INSERT ON events
+    FOR EACH ROW EXECUTE PROCEDURE notify_event_change();
+
+DROP TRIGGER IF EXISTS event_state_changed ON events;
+CREATE TRIGGER event_state_changed
+    AFTER UPDATE OF state ON events
+    FOR EACH ROW
+    WHEN (OLD.state IS DISTINCT FROM NEW.state)
+    EXECUTE PROCEDURE notify_event_change();
\ No newline at end of file
@@ -1,4 +1,5 @@
 CREATE INDEX IF NOT EXISTS key_idx ON events(namespace, key);
 CREATE INDEX IF NOT EXISTS key_state_idx ON events(namespace, key, id, state);
 CREATE INDEX IF NOT EXISTS state_idx ON events(namespace, scheduled_at, state);
+CREATE INDEX IF NOT EXISTS series_idx ON events(series_id, created_at);
 CREATE UNIQUE INDEX IF NOT EXISTS single_scheduled_idx ON events(namespace, key) WHERE state = 'SCHEDULED';
\ No newline at end of file
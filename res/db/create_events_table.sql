@@ -8,5 +8,24 @@ CREATE TABLE IF NOT EXISTS events(
     namespace              VARCHAR(64)                     NOT NULL,
     state                  state                           NOT NULL DEFAULT 'SCHEDULED',
     created_at             TIMESTAMP WITH TIME ZONE        NOT NULL DEFAULT CURRENT_TIMESTAMP,
-    scheduled_at           TIMESTAMP WITH TIME ZONE        NOT NULL
-);
\ No newline at end of file
+    scheduled_at           TIMESTAMP WITH TIME ZONE        NOT NULL,
+    version                BIGINT                          NOT NULL DEFAULT 1,
+    previous_id            UUID                            NULL,
+    series_id              UUID                            NOT NULL
+);
+
+ALTER TABLE events ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE events ADD COLUMN IF NOT EXISTS previous_id UUID NULL;
+
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_name = 'events'
+        AND column_name = 'series_id'
+    ) THEN
+        ALTER TABLE events ADD COLUMN series_id UUID NULL;
+        UPDATE events SET series_id = id;
+        ALTER TABLE events ALTER COLUMN series_id SET NOT NULL;
+    END IF;
+END $$;
\ No newline at end of file
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS retention_policies(
+    namespace              VARCHAR(64)                     NOT NULL PRIMARY KEY,
+    max_age_seconds        BIGINT                          NULL CHECK (max_age_seconds >= 0),
+    max_count              BIGINT                          NULL CHECK (max_count >= 0)
+);
\ No newline at end of file
@@ -0,0 +1,11 @@
+-- PREPARE disable_event (uuid, text) AS
+
+WITH updated AS (
+    UPDATE events
+    SET state = 'DISABLED', version = version + 1
+    WHERE id = $1
+    AND state = 'SCHEDULED'
+    RETURNING id
+)
+INSERT INTO event_transitions(event_id, old_state, new_state, changed_by)
+SELECT id, 'SCHEDULED', 'DISABLED', CAST($2 AS VARCHAR) FROM updated;
\ No newline at end of file
@@ -0,0 +1,11 @@
+-- PREPARE due_events (text, timestamptz, uuid, timestamptz, bigint) AS
+
+SELECT id, key, value, idempotence_key, namespace, state, created_at, scheduled_at, version,
+    previous_id, series_id
+FROM events
+WHERE namespace = $1
+AND state = 'SCHEDULED'
+AND (scheduled_at, id) > ($2, $3)
+AND scheduled_at <= $4
+ORDER BY scheduled_at ASC, id ASC
+LIMIT $5;
\ No newline at end of file
@@ -0,0 +1,14 @@
+-- PREPARE event_lineage (text, uuid) AS
+
+WITH RECURSIVE lineage AS (
+    SELECT e.*, 0 AS depth FROM events e
+    WHERE e.namespace = $1
+    AND e.id = $2
+    UNION ALL
+    SELECT e.*, l.depth + 1 FROM events e
+    JOIN lineage l ON e.id = l.previous_id
+)
+SELECT id, key, value, idempotence_key, namespace, state, created_at, scheduled_at, version,
+    previous_id, series_id
+FROM lineage
+ORDER BY depth ASC;
\ No newline at end of file
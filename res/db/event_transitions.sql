@@ -0,0 +1,8 @@
+-- PREPARE event_transitions (text, uuid) AS
+
+SELECT t.event_id, t.old_state, t.new_state, t.changed_at, t.changed_by
+FROM event_transitions t
+JOIN events e ON e.id = t.event_id
+WHERE e.namespace = $1
+AND t.event_id = $2
+ORDER BY t.id ASC;
\ No newline at end of file
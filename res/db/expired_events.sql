@@ -0,0 +1,16 @@
+-- PREPARE expired_events (text) AS
+
+WITH ranked AS (
+    SELECT e.id, e.namespace, e.scheduled_at, p.max_age_seconds, p.max_count,
+        ROW_NUMBER() OVER (PARTITION BY e.namespace, e.key ORDER BY e.scheduled_at DESC) AS n
+    FROM events e
+    JOIN retention_policies p ON p.namespace = e.namespace
+    WHERE e.state IN ('COMPLETED', 'DISABLED')
+    AND (e.namespace = $1 OR $1 IS NULL)
+)
+SELECT namespace, COUNT(*) AS expired
+FROM ranked
+WHERE scheduled_at < CURRENT_TIMESTAMP - max_age_seconds * INTERVAL '1 second'
+OR n > max_count
+GROUP BY namespace
+ORDER BY namespace;
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- PREPARE finished_events (timestamp, text, bigint) AS
+
+SELECT * FROM events
+WHERE state IN ('COMPLETED', 'DISABLED')
+AND scheduled_at < $1
+AND (namespace = $2 OR $2 IS NULL)
+ORDER BY scheduled_at ASC
+LIMIT $3
+FOR UPDATE SKIP LOCKED;
\ No newline at end of file
@@ -1,5 +1,17 @@
--- PREPARE insert_event(text, text, timestamp, json) AS
+-- PREPARE insert_event(text, text, timestamp, json, uuid, uuid) AS
 
-INSERT INTO events(key, namespace, scheduled_at, value)
-VALUES($1, $2, $3, $4)
-RETURNING id, key, value, idempotence_key, namespace, state, created_at, scheduled_at;
\ No newline at end of file
+WITH new AS (SELECT uuid_generate_v4() AS id)
+INSERT INTO events(id, key, namespace, scheduled_at, value, idempotence_key, previous_id, series_id)
+VALUES(
+    (SELECT id FROM new),
+    $1,
+    $2,
+    $3,
+    $4,
+    COALESCE($5, uuid_generate_v4()),
+    $6,
+    COALESCE((SELECT series_id FROM events WHERE id = $6), (SELECT id FROM new))
+)
+ON CONFLICT (idempotence_key) DO NOTHING
+RETURNING id, key, value, idempotence_key, namespace, state, created_at, scheduled_at, version,
+    previous_id, series_id;
\ No newline at end of file
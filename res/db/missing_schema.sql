@@ -0,0 +1,12 @@
+SELECT required.name
+FROM (VALUES ('events'), ('event_transitions'), ('retention_policies'), ('single_scheduled_idx')) AS required(name)
+WHERE to_regclass(required.name) IS NULL
+UNION ALL
+SELECT 'state'
+WHERE to_regtype('state') IS NULL
+UNION ALL
+SELECT required.name
+FROM (VALUES ('event_inserted'), ('event_state_changed')) AS required(name)
+WHERE NOT EXISTS (
+    SELECT 1 FROM pg_trigger WHERE tgrelid = to_regclass('events') AND tgname = required.name
+)
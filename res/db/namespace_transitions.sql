@@ -0,0 +1,9 @@
+-- PREPARE namespace_transitions (text, bigint, bigint) AS
+
+SELECT t.event_id, t.old_state, t.new_state, t.changed_at, t.changed_by, t.id
+FROM event_transitions t
+JOIN events e ON e.id = t.event_id
+WHERE e.namespace = $1
+AND t.id > $2
+ORDER BY t.id ASC
+LIMIT $3;
\ No newline at end of file
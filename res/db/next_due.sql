@@ -0,0 +1,7 @@
+-- PREPARE next_due (text, timestamptz) AS
+
+SELECT MIN(scheduled_at)
+FROM events
+WHERE namespace = $1
+AND state = 'SCHEDULED'
+AND scheduled_at > $2;
\ No newline at end of file
@@ -0,0 +1,11 @@
+-- Oldest overdue event per namespace, walking state_idx in (namespace, scheduled_at) order
+
+SELECT DISTINCT ON (namespace)
+    id, key, value, idempotence_key, namespace, state, created_at, scheduled_at, version,
+    previous_id, series_id,
+    COUNT(*) OVER (PARTITION BY namespace) AS overdue,
+    CAST(EXTRACT(EPOCH FROM (CURRENT_TIMESTAMP - scheduled_at)) * 1000 AS BIGINT) AS max_lag_ms
+FROM events
+WHERE state = 'SCHEDULED'
+AND scheduled_at < CURRENT_TIMESTAMP
+ORDER BY namespace, scheduled_at ASC;
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- PREPARE restore_event (uuid, text, json, uuid, text, state, timestamp, timestamp, bigint, uuid, uuid) AS
+
+INSERT INTO events(
+    id, key, value, idempotence_key, namespace, state, created_at, scheduled_at, version,
+    previous_id, series_id
+)
+VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+ON CONFLICT (id) DO NOTHING
+RETURNING id;
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- PREPARE restore_transition (uuid, state, state, timestamp, text) AS
+
+INSERT INTO event_transitions(event_id, old_state, new_state, changed_at, changed_by)
+VALUES($1, $2, $3, $4, $5);
@@ -0,0 +1,6 @@
+-- PREPARE series_events (text, uuid) AS
+
+SELECT * FROM events
+WHERE namespace = $1
+AND series_id = $2
+ORDER BY created_at ASC;
\ No newline at end of file
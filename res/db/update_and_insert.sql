@@ -3,7 +3,7 @@ BEGIN;
 -- EXECUTE update_event($1, $2, $3, $4);
 
 UPDATE events
-SET state = $1
+SET state = $1, version = version + 1
 WHERE id = $2
 AND key = $3
 AND namespace = $4
@@ -15,7 +15,8 @@ AND state <> 'COMPLETED';
 
 INSERT INTO events(key, namespace, scheduled_at, value)
 VALUES($3, $4, $5, $6)
-RETURNING id, key, value, idempotence_key, namespace, state, created_at, scheduled_at;
+RETURNING id, key, value, idempotence_key, namespace, state, created_at, scheduled_at, version,
+    previous_id, series_id;
 
 
 COMMIT;
\ No newline at end of file
@@ -1,9 +1,25 @@
--- PREPARE update_event (state, uuid, text, text) AS
+-- PREPARE update_event (state, uuid, text, text, bigint, text) AS
 
-UPDATE events
-SET state = $1
-WHERE id = $2
-AND key = $3
-AND namespace = $4
-AND state <> 'COMPLETED'
-RETURNING *;
\ No newline at end of file
+WITH old AS (
+    SELECT id, state FROM events
+    WHERE id = $2
+    AND key = $3
+    AND namespace = $4
+    FOR UPDATE
+), updated AS (
+    UPDATE events e
+    SET state = $1, version = e.version + 1
+    FROM old
+    WHERE e.id = old.id
+    AND e.state <> 'COMPLETED'
+    AND e.state <> $1
+    AND (e.version = $5 OR $5 IS NULL)
+    RETURNING e.id, e.key, e.value, e.idempotence_key, e.namespace, e.state, e.created_at,
+        e.scheduled_at, e.version, e.previous_id, e.series_id, old.state AS old_state
+), transition AS (
+    INSERT INTO event_transitions(event_id, old_state, new_state, changed_by)
+    SELECT id, old_state, state, CAST($6 AS VARCHAR) FROM updated
+)
+SELECT id, key, value, idempotence_key, namespace, state, created_at, scheduled_at, version,
+    previous_id, series_id
+FROM updated;
\ No newline at end of file
@@ -0,0 +1,234 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ArchiveArgs, RestoreArgs};
+use crate::db::event::{EventRepoPgsql, RepoErr};
+use crate::event::{Event, Transition};
+
+/// An event as it is written to an archive, along with the history of its state changes, which
+/// would otherwise be lost when the event is deleted from the database. Archives written before
+/// the history was included have no transitions.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchivedEvent {
+    #[serde(flatten)]
+    event: Event,
+    #[serde(default)]
+    transitions: Vec<Transition>,
+}
+
+impl ArchivedEvent {
+    pub fn new(event: Event, transitions: Vec<Transition>) -> ArchivedEvent {
+        ArchivedEvent { event, transitions }
+    }
+
+    pub fn event(&self) -> &Event {
+        &self.event
+    }
+
+    pub fn transitions(&self) -> &[Transition] {
+        &self.transitions
+    }
+}
+
+/// Writes events as JSON Lines to gzip compressed files in a directory, starting a new file each
+/// time the current one has reached the maximum number of events.
+pub struct ArchiveWriter {
+    dir: PathBuf,
+    prefix: String,
+    max_events: usize,
+    files: Vec<PathBuf>,
+    current: Option<(GzEncoder<File>, usize)>,
+}
+
+impl ArchiveWriter {
+    pub fn new(dir: &Path, max_events: usize) -> ArchiveWriter {
+        let prefix: String = chrono::Utc::now()
+            .format("events-%Y%m%dT%H%M%SZ")
+            .to_string();
+        ArchiveWriter {
+            dir: dir.to_path_buf(),
+            prefix,
+            max_events: max_events.max(1),
+            files: Vec::new(),
+            current: None,
+        }
+    }
+
+    /// Write events to the archive, and make sure they are persisted to disk before returning.
+    pub fn write(&mut self, events: &[ArchivedEvent]) -> io::Result<()> {
+        for event in events {
+            let (encoder, count) = match &mut self.current {
+                Some((_, count)) if *count < self.max_events => self.current.as_mut().unwrap(),
+                _ => self.rotate()?,
+            };
+
+            serde_json::to_writer(&mut *encoder, event)?;
+            encoder.write_all(b"\n")?;
+            *count += 1;
+        }
+
+        if let Some((encoder, _)) = &mut self.current {
+            encoder.flush()?;
+            encoder.get_ref().sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    /// Complete the last archive file, returning the paths of all files written to.
+    pub fn finish(mut self) -> io::Result<Vec<PathBuf>> {
+        if let Some((encoder, _)) = self.current.take() {
+            encoder.finish()?.sync_all()?;
+        }
+
+        Ok(self.files)
+    }
+
+    fn rotate(&mut self) -> io::Result<&mut (GzEncoder<File>, usize)> {
+        if let Some((encoder, _)) = self.current.take() {
+            encoder.finish()?.sync_all()?;
+        }
+
+        let name: String = format!("{}-{:04}.jsonl.gz", self.prefix, self.files.len());
+        let path: PathBuf = self.dir.join(name);
+        let file: File = File::options().write(true).create_new(true).open(&path)?;
+        info!("Archiving events to {}", path.display());
+
+        self.files.push(path);
+        Ok(self
+            .current
+            .insert((GzEncoder::new(file, Compression::default()), 0)))
+    }
+}
+
+/// Move finished events older than the configured threshold from the database to archive files.
+pub async fn archive_cmd(repo: &EventRepoPgsql, args: &ArchiveArgs) -> Result<(), RepoErr> {
+    let older_than = match chrono::Duration::from_std(args.older_than()) {
+        Ok(duration) => duration,
+        Err(e) => return Err(RepoErr::Other(e.to_string())),
+    };
+    let before: chrono::DateTime<chrono::Utc> = chrono::Utc::now() - older_than;
+
+    if let Err(e) = std::fs::create_dir_all(args.dir()) {
+        return Err(RepoErr::Other(e.to_string()));
+    }
+
+    let mut writer = ArchiveWriter::new(args.dir(), args.max_file_events());
+    let mut archived: usize = 0;
+
+    loop {
+        let count: usize = repo
+            .archive_finished(before, args.namespace(), args.batch_size(), |events| {
+                writer.write(events)
+            })
+            .await?;
+
+        archived += count;
+
+        if (count as i64) < args.batch_size() {
+            break;
+        }
+    }
+
+    let files: Vec<PathBuf> = match writer.finish() {
+        Ok(files) => files,
+        Err(e) => return Err(RepoErr::Other(e.to_string())),
+    };
+
+    println!("Archived {} events", archived);
+    for file in files {
+        println!("{}", file.display());
+    }
+
+    Ok(())
+}
+
+/// Insert all events from the given archive files back into the database. Events that already
+/// exist are left untouched, so restoring the same archive more than once is safe. Fails if any
+/// event could not be restored, after restoring all others.
+pub async fn restore_cmd(repo: &EventRepoPgsql, args: &RestoreArgs) -> Result<(), RepoErr> {
+    let mut total_failed: usize = 0;
+
+    for path in args.files() {
+        let file: File = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => return Err(RepoErr::Other(format!("{}: {}", path.display(), e))),
+        };
+
+        let reader = BufReader::new(MultiGzDecoder::new(file));
+        let source: String = path.display().to_string();
+        let (restored, skipped, failed) = restore_events(repo, reader, &source).await;
+
+        println!(
+            "{}: restored {} events, skipped {} already present, {} failed",
+            source, restored, skipped, failed
+        );
+        total_failed += failed;
+    }
+
+    match total_failed {
+        0 => Ok(()),
+        failed => Err(RepoErr::Other(format!(
+            "{} events failed to restore",
+            failed
+        ))),
+    }
+}
+
+/// Insert events, one JSON object per line, into the database unless an event with the same id
+/// already exists, along with their transitions if they were archived with them. Returns the
+/// number of events that were restored, skipped and failed.
+pub async fn restore_events<R: BufRead>(
+    repo: &EventRepoPgsql,
+    reader: R,
+    source: &str,
+) -> (usize, usize, usize) {
+    let (mut restored, mut skipped, mut failed) = (0, 0, 0);
+
+    for (n, line) in reader.lines().enumerate() {
+        let line: String = match line {
+            Ok(line) => line,
+            Err(e) => {
+                error!("{}: unable to read line {}: {}", source, n + 1, e);
+                failed += 1;
+                break;
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let event: ArchivedEvent = match serde_json::from_str(&line) {
+            Ok(event) => event,
+            Err(e) => {
+                error!("{}: invalid event on line {}: {}", source, n + 1, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        match repo.restore(&event).await {
+            Ok(true) => restored += 1,
+            Ok(false) => skipped += 1,
+            Err(e) => {
+                error!(
+                    "{}: unable to restore event {}: {:?}",
+                    source,
+                    event.event().id(),
+                    e
+                );
+                failed += 1;
+            }
+        }
+    }
+
+    (restored, skipped, failed)
+}
@@ -1,6 +1,10 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 
-use crate::logger::Verbosity;
+use crate::event::State;
+use crate::ics;
+use crate::logger::{LogFormat, Verbosity};
+use crate::search::SearchQuery;
+use crate::trace::Export;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
@@ -8,12 +12,292 @@ pub struct Config {
     #[clap(short, long, env = "DB_URL")]
     database: String,
 
+    /// Maximum number of connections to the database
+    #[clap(long, env = "DB_POOL_SIZE", default_value = "16")]
+    db_pool_size: usize,
+
+    /// Longest time to wait for a database connection, such as "5s" or "500ms"
+    #[clap(long, env = "DB_POOL_TIMEOUT", default_value = "5s", parse(try_from_str = humantime::parse_duration))]
+    db_pool_timeout: std::time::Duration,
+
+    /// PEM file with the CA certificate to verify the database server certificate against
+    ///
+    /// Whether TLS is used is set by sslmode in the connection string. Without a CA certificate,
+    /// the connection is encrypted but the server certificate is not verified.
+    #[clap(long, env = "DB_CA_CERT")]
+    db_ca_cert: Option<std::path::PathBuf>,
+
     /// Set verbosity level, 0 - 5
     ///
     /// Set the verbosity level, from 0 (least amount of output) to 5 (most verbose). Note that
     /// logging level configured via RUST_LOG overrides this setting.
     #[structopt(short, long = "verbosity", default_value = "1")]
     verbosity_level: u8,
+
+    /// Format of log output, either "text" for reading in a terminal or "json" for one JSON
+    /// object per line
+    #[clap(long, env = "LOG_FORMAT", default_value = "text", possible_values = ["text", "json"])]
+    log_format: LogFormat,
+
+    /// Seconds between each purge of expired events
+    ///
+    /// How often finished events that have expired according to the retention policy of their
+    /// namespace are purged. Set to 0 to disable purging while the server is running.
+    #[clap(long, env = "REAP_INTERVAL", default_value = "300")]
+    reap_interval: u64,
+
+    /// Maximum number of events to delete in a single statement when purging
+    #[clap(long, env = "REAP_BATCH_SIZE", default_value = "500")]
+    reap_batch_size: std::num::NonZeroU32,
+
+    /// Seconds between each count of events by state and of overdue events for /metrics
+    ///
+    /// Set to 0 to disable sampling, leaving those gauges out of /metrics.
+    #[clap(long, env = "METRICS_INTERVAL", default_value = "15")]
+    metrics_interval: u64,
+
+    /// Address to serve the HTTP and gRPC APIs on
+    #[clap(long, env = "BIND", default_value = "127.0.0.1")]
+    bind: std::net::IpAddr,
+
+    /// Port to serve the HTTP API on
+    #[clap(long, env = "PORT", default_value = "3000")]
+    port: u16,
+
+    /// Serve the HTTP API on this Unix domain socket instead of the TCP port
+    #[clap(long, env = "UNIX_SOCKET", conflicts_with = "tls-cert")]
+    unix_socket: Option<std::path::PathBuf>,
+
+    /// PEM file with the certificate chain to serve TLS with
+    ///
+    /// When set along with the key, both the HTTP and gRPC APIs are served over TLS only. The
+    /// certificate and key are read again when the process receives SIGHUP.
+    #[clap(long, env = "TLS_CERT", requires = "tls-key")]
+    tls_cert: Option<std::path::PathBuf>,
+
+    /// PEM file with the private key of the TLS certificate
+    #[clap(long, env = "TLS_KEY", requires = "tls-cert")]
+    tls_key: Option<std::path::PathBuf>,
+
+    /// Port to serve the gRPC API on, or 0 to not serve it
+    #[clap(long, env = "GRPC_PORT", default_value = "50051")]
+    grpc_port: u16,
+
+    /// Export traces to an OpenTelemetry collector at this OTLP/gRPC endpoint, such as
+    /// "http://localhost:4317"
+    #[clap(long, env = "OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+
+    /// Export traces to this file, with one OTLP/JSON export request per line
+    #[clap(long, env = "TRACE_FILE", conflicts_with = "otlp-endpoint")]
+    trace_file: Option<std::path::PathBuf>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Purge finished events that have expired according to the retention policies
+    Purge(PurgeArgs),
+    /// Move old finished events from the database to compressed archive files
+    Archive(ArchiveArgs),
+    /// Insert events from archive files back into the database
+    Restore(RestoreArgs),
+    /// Write events as JSON Lines
+    Export(ExportArgs),
+    /// Read events as JSON Lines, skipping events that already exist
+    Import(ImportArgs),
+    /// Schedule events for the occurrences of VEVENTs in an iCalendar file
+    ImportIcs(ImportIcsArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct PurgeArgs {
+    /// Only purge events in this namespace
+    #[clap(short, long)]
+    namespace: Option<String>,
+
+    /// Show how many events would be purged, without deleting anything
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Maximum number of events to delete in a single statement
+    #[clap(long, default_value = "500")]
+    batch_size: std::num::NonZeroU32,
+}
+
+#[derive(Args, Debug)]
+pub struct ArchiveArgs {
+    /// Archive finished events scheduled longer ago than this, such as "30d" or "12h"
+    #[clap(long, parse(try_from_str = humantime::parse_duration))]
+    older_than: std::time::Duration,
+
+    /// Directory where archive files are written
+    #[clap(short, long, default_value = ".")]
+    dir: std::path::PathBuf,
+
+    /// Only archive events in this namespace
+    #[clap(short, long)]
+    namespace: Option<String>,
+
+    /// Maximum number of events in each archive file before starting a new one
+    #[clap(long, default_value = "100000")]
+    max_file_events: usize,
+
+    /// Maximum number of events to archive in a single transaction
+    #[clap(long, default_value = "500")]
+    batch_size: std::num::NonZeroU32,
+}
+
+impl ArchiveArgs {
+    pub fn older_than(&self) -> std::time::Duration {
+        self.older_than
+    }
+
+    pub fn dir(&self) -> &std::path::Path {
+        &self.dir
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    pub fn max_file_events(&self) -> usize {
+        self.max_file_events
+    }
+
+    pub fn batch_size(&self) -> i64 {
+        self.batch_size.get().into()
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct RestoreArgs {
+    /// Archive files to restore events from
+    #[clap(required = true)]
+    files: Vec<std::path::PathBuf>,
+}
+
+impl RestoreArgs {
+    pub fn files(&self) -> &[std::path::PathBuf] {
+        &self.files
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    /// Namespace to export events from
+    #[clap(short, long)]
+    namespace: String,
+
+    /// Only export events with this key
+    #[clap(short, long)]
+    key: Option<String>,
+
+    /// Only export events in this state, may be given more than once [default: SCHEDULED]
+    #[clap(short, long)]
+    state: Vec<State>,
+
+    /// Only export events scheduled at or after this time (RFC 3339)
+    #[clap(long)]
+    scheduled_at_min: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Only export events scheduled before this time (RFC 3339)
+    #[clap(long)]
+    scheduled_at_max: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Maximum number of events to export, all matching events are exported if not set
+    #[clap(long)]
+    limit: Option<u32>,
+
+    /// File to write events to, instead of stdout
+    #[clap(short, long)]
+    output: Option<std::path::PathBuf>,
+}
+
+impl ExportArgs {
+    pub fn query(&self) -> SearchQuery {
+        let state: Option<Vec<State>> = match self.state.is_empty() {
+            true => None,
+            false => Some(self.state.clone()),
+        };
+
+        SearchQuery::new(
+            self.namespace.clone(),
+            self.key.clone(),
+            state,
+            self.limit,
+            self.scheduled_at_min,
+            self.scheduled_at_max,
+        )
+    }
+
+    pub fn output(&self) -> Option<&std::path::Path> {
+        self.output.as_deref()
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ImportArgs {
+    /// File to read events from, instead of stdin
+    input: Option<std::path::PathBuf>,
+}
+
+impl ImportArgs {
+    pub fn input(&self) -> Option<&std::path::Path> {
+        self.input.as_deref()
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ImportIcsArgs {
+    /// Namespace to schedule the events in
+    #[clap(short, long)]
+    namespace: String,
+
+    /// Only schedule occurrences within this long from now, such as "90d" or "12h", up to a year
+    #[clap(long, default_value = "90d", parse(try_from_str = parse_horizon))]
+    horizon: std::time::Duration,
+
+    /// Show which events would be scheduled, without scheduling anything
+    #[clap(long)]
+    dry_run: bool,
+
+    /// iCalendar file to read, instead of stdin
+    input: Option<std::path::PathBuf>,
+}
+
+impl ImportIcsArgs {
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn horizon(&self) -> std::time::Duration {
+        self.horizon
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn input(&self) -> Option<&std::path::Path> {
+        self.input.as_deref()
+    }
+}
+
+impl PurgeArgs {
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn batch_size(&self) -> i64 {
+        self.batch_size.get().into()
+    }
 }
 
 impl Config {
@@ -21,9 +305,90 @@ impl Config {
         &self.database
     }
 
+    pub fn db_pool_size(&self) -> usize {
+        self.db_pool_size
+    }
+
+    pub fn db_pool_timeout(&self) -> std::time::Duration {
+        self.db_pool_timeout
+    }
+
+    pub fn db_ca_cert(&self) -> Option<&std::path::Path> {
+        self.db_ca_cert.as_deref()
+    }
+
+    pub fn command(&self) -> Option<&Command> {
+        self.command.as_ref()
+    }
+
+    /// Interval between each purge of expired events, or `None` if purging is disabled
+    pub fn reap_interval(&self) -> Option<std::time::Duration> {
+        match self.reap_interval {
+            0 => None,
+            secs => Some(std::time::Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn reap_batch_size(&self) -> i64 {
+        self.reap_batch_size.get().into()
+    }
+
+    /// Interval between each sample of the event gauges, or `None` if sampling is disabled
+    pub fn metrics_interval(&self) -> Option<std::time::Duration> {
+        match self.metrics_interval {
+            0 => None,
+            secs => Some(std::time::Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn http_addr(&self) -> std::net::SocketAddr {
+        std::net::SocketAddr::new(self.bind, self.port)
+    }
+
+    pub fn unix_socket(&self) -> Option<&std::path::Path> {
+        self.unix_socket.as_deref()
+    }
+
+    /// Paths of the TLS certificate chain and private key, if TLS is enabled
+    pub fn tls(&self) -> Option<(&std::path::Path, &std::path::Path)> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            _ => None,
+        }
+    }
+
+    /// Address to serve the gRPC API on, or `None` if it is disabled
+    pub fn grpc_addr(&self) -> Option<std::net::SocketAddr> {
+        match self.grpc_port {
+            0 => None,
+            port => Some(std::net::SocketAddr::new(self.bind, port)),
+        }
+    }
+
+    /// Where traces are exported to, or `None` if they are not exported
+    pub fn trace_export(&self) -> Option<Export<'_>> {
+        match (&self.otlp_endpoint, &self.trace_file) {
+            (Some(endpoint), _) => Some(Export::Collector(endpoint)),
+            (None, Some(path)) => Some(Export::File(path)),
+            (None, None) => None,
+        }
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format
+    }
+
     pub fn verbosity(&self) -> Verbosity {
         self.verbosity_level
             .try_into()
             .expect("Invalid vervosity level")
     }
 }
+
+fn parse_horizon(value: &str) -> Result<std::time::Duration, String> {
+    let horizon = humantime::parse_duration(value).map_err(|e| e.to_string())?;
+    match horizon <= ics::MAX_HORIZON {
+        true => Ok(horizon),
+        false => Err(String::from("Horizon is longer than 365 days")),
+    }
+}
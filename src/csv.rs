@@ -0,0 +1,198 @@
+use crate::event::Event;
+
+/// Fields of an event that are always included, by their name in the JSON representation
+const EVENT_COLUMNS: [&str; 10] = [
+    "id",
+    "namespace",
+    "key",
+    "state",
+    "scheduledAt",
+    "createdAt",
+    "version",
+    "idempotenceKey",
+    "previousId",
+    "seriesId",
+];
+
+/// Characters that make a spreadsheet read a cell as a formula, when the cell starts with them
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// Columns of a CSV file with events, where the fields of each event are followed either by its
+/// whole `value` as JSON, or by one column for each selected path into the `value`. Paths are
+/// separated by dots, such as `customer.id` or `items.0.sku`.
+#[derive(Debug, Clone)]
+pub struct Columns {
+    paths: Vec<String>,
+}
+
+impl Columns {
+    pub fn new(paths: &[String]) -> Columns {
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|path| path.trim())
+            .filter(|path| !path.is_empty())
+            .map(String::from)
+            .collect();
+
+        Columns { paths }
+    }
+
+    pub fn header(&self) -> String {
+        let mut header: Vec<String> = EVENT_COLUMNS.iter().map(|c| c.to_string()).collect();
+        match self.paths.is_empty() {
+            true => header.push(String::from("value")),
+            false => header.extend(self.paths.iter().map(|path| format!("value.{}", path))),
+        }
+
+        line(header.iter().map(String::as_str))
+    }
+
+    pub fn row(&self, event: &Event) -> String {
+        let fields: serde_json::Value = serde_json::to_value(event).unwrap();
+        let mut row: Vec<String> = EVENT_COLUMNS
+            .iter()
+            .map(|column| cell(fields.get(*column)))
+            .collect();
+
+        match self.paths.is_empty() {
+            true => row.push(event.value().to_string()),
+            false => row.extend(
+                self.paths
+                    .iter()
+                    .map(|path| cell(event.value().pointer(&pointer(path)))),
+            ),
+        }
+
+        line(row.iter().map(String::as_str))
+    }
+}
+
+/// JSON pointer for a path separated by dots, where each part is the name of a field or the index
+/// of an array element
+fn pointer(path: &str) -> String {
+    path.split('.')
+        .map(|part| format!("/{}", part.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// Text of a single cell, where strings are written as they are, objects and arrays as JSON, and
+/// missing values and nulls as empty cells. Strings that a spreadsheet would take for a formula
+/// are prefixed with a quote, so that opening the file does not run them.
+fn cell(value: Option<&serde_json::Value>) -> String {
+    match value {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(text)) if text.starts_with(FORMULA_PREFIXES) => {
+            format!("'{}", text)
+        }
+        Some(serde_json::Value::String(text)) => text.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// A line of comma separated cells, quoted as described in RFC 4180
+fn line<'a, I: Iterator<Item = &'a str>>(cells: I) -> String {
+    let mut line = String::new();
+    for (i, cell) in cells.enumerate() {
+        if i > 0 {
+            line.push(',');
+        }
+
+        if cell.contains([',', '"', '\n', '\r']) {
+            line.push('"');
+            line.push_str(&cell.replace('"', "\"\""));
+            line.push('"');
+        } else {
+            line.push_str(cell);
+        }
+    }
+    line.push_str("\r\n");
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(value: serde_json::Value) -> Event {
+        serde_json::from_value(serde_json::json!({
+            "key": "=HYPERLINK(\"http://example.com\")",
+            "value": value,
+            "id": "00000000-0000-0000-0000-000000000001",
+            "namespace": "billing",
+            "idempotenceKey": "00000000-0000-0000-0000-000000000002",
+            "state": "Scheduled",
+            "createdAt": "2026-01-01T00:00:00Z",
+            "scheduledAt": "2026-01-02T00:00:00Z",
+            "version": 1,
+            "previousId": null,
+            "seriesId": "00000000-0000-0000-0000-000000000001",
+        }))
+        .unwrap()
+    }
+
+    fn paths(paths: &[&str]) -> Columns {
+        let paths: Vec<String> = paths.iter().map(|path| path.to_string()).collect();
+        Columns::new(&paths)
+    }
+
+    #[test]
+    fn header_with_whole_value() {
+        assert_eq!(
+            paths(&[]).header(),
+            "id,namespace,key,state,scheduledAt,createdAt,version,idempotenceKey,previousId,seriesId,value\r\n"
+        );
+    }
+
+    #[test]
+    fn header_with_paths() {
+        let header: String = paths(&[" customer.id", "", "items.0.sku "]).header();
+        assert!(header.ends_with(",seriesId,value.customer.id,value.items.0.sku\r\n"));
+    }
+
+    #[test]
+    fn row_with_paths() {
+        let event = event(serde_json::json!({
+            "customer": { "id": 7, "name": "Doe, Jane" },
+            "items": [{ "sku": "A-1" }],
+            "a/b": { "~c": true },
+        }));
+        let row: String = paths(&[
+            "customer.id",
+            "customer.name",
+            "items.0.sku",
+            "missing",
+            "a/b.~c",
+        ])
+        .row(&event);
+
+        assert!(row.ends_with(",7,\"Doe, Jane\",A-1,,true\r\n"), "{}", row);
+    }
+
+    #[test]
+    fn row_with_whole_value() {
+        let row: String = paths(&[]).row(&event(serde_json::json!({ "n": 1 })));
+        assert!(row.ends_with(",\"{\"\"n\"\":1}\"\r\n"), "{}", row);
+    }
+
+    #[test]
+    fn formulas_are_neutralised() {
+        let event = event(serde_json::json!({
+            "plus": "+1", "minus": "-1", "at": "@SUM(A1)", "tab": "\tx", "cr": "\rx",
+            "number": -1, "text": "a=b",
+        }));
+        let row: String =
+            paths(&["plus", "minus", "at", "tab", "cr", "number", "text"]).row(&event);
+        let cells: Vec<&str> = row.trim_end().split(',').collect();
+
+        assert_eq!(cells[2], "\"'=HYPERLINK(\"\"http://example.com\"\")\"");
+        assert_eq!(cells[10..13], ["'+1", "'-1", "'@SUM(A1)"]);
+        assert_eq!(cells[13], "'\tx");
+        assert_eq!(cells[14], "\"'\rx\"");
+        assert_eq!(cells[15..], ["-1", "a=b"]);
+    }
+
+    #[test]
+    fn pointer_escapes_segments() {
+        assert_eq!(pointer("a/b.~c.0"), "/a~1b/~0c/0");
+    }
+}
@@ -1,76 +1,198 @@
 pub mod event {
-    use std::sync::Arc;
+    use std::collections::BTreeMap;
+    use std::future::Future;
+    use std::time::Instant;
 
-    use log::info;
+    use deadpool_postgres::{ClientWrapper, Object, Pool, PoolError, Transaction};
+    use futures::{Stream, StreamExt};
+    use log::{debug, info};
     use postgres_types::ToSql;
-    use tokio::sync::Mutex;
-    use tokio::sync::MutexGuard;
-    use tokio_postgres::GenericClient;
-    use tokio_postgres::{error::DbError, Client, Row, Statement, Transaction};
+    use tokio_postgres::{error::DbError, GenericClient, Row, RowStream, Statement};
+    use tracing::{info_span, Instrument};
 
     use crate::{
-        event::{Event, State},
-        http::event::{CreateEvent, SettleAndNextEvent, SettleEvent},
+        archive::ArchivedEvent,
+        event::{Event, Overdue, State, Transition},
+        http::event::{BatchMode, Conflict, CreateEvent, SettleAndNextEvent, SettleEvent},
+        metrics,
+        retention::RetentionPolicy,
         search::SearchQuery,
     };
 
-    #[derive(Clone)]
-    pub struct EventRepoPgsql {
-        client: Arc<tokio_postgres::Client>,
-        client_trx: Arc<Mutex<tokio_postgres::Client>>,
-        insert_stmt: Statement,
-        update_stmt: Statement,
-        search_stmt: Statement,
+    const INSERT_EVENT: &str = include_str!("../res/db/insert_event.sql");
+    const UPDATE_EVENT: &str = include_str!("../res/db/update_event.sql");
+    const SEARCH_EVENTS: &str = include_str!("../res/db/search_events.sql");
+    const OVERDUE_EVENTS: &str = include_str!("../res/db/overdue_events.sql");
+    const EVENT_TRANSITIONS: &str = include_str!("../res/db/event_transitions.sql");
+    const SERIES_EVENTS: &str = include_str!("../res/db/series_events.sql");
+    const EVENT_LINEAGE: &str = include_str!("../res/db/event_lineage.sql");
+    const DUE_EVENTS: &str = include_str!("../res/db/due_events.sql");
+    const NEXT_DUE: &str = include_str!("../res/db/next_due.sql");
+    const NAMESPACE_TRANSITIONS: &str = include_str!("../res/db/namespace_transitions.sql");
+
+    /// Run a repository call in a span of its own, within the span of the request it is made
+    /// for, and time it
+    async fn traced<T, F>(name: &'static str, call: F) -> Result<T, RepoErr>
+    where
+        F: Future<Output = Result<T, RepoErr>>,
+    {
+        let span = info_span!("query", db.operation = name, otel.kind = "client");
+        metrics::time_query(name, call).instrument(span).await
     }
 
-    impl EventRepoPgsql {
-        pub async fn new(
-            client: Arc<tokio_postgres::Client>,
-            client_trx: Arc<Mutex<tokio_postgres::Client>>,
-        ) -> Result<EventRepoPgsql, tokio_postgres::Error> {
-            let insert_stmt = client
-                .prepare(include_str!("../res/db/insert_event.sql"))
-                .await?;
+    /// Statements that are prepared once per connection and then reused, by name
+    const STATEMENTS: [(&str, &str); 10] = [
+        ("insert_event", INSERT_EVENT),
+        ("update_event", UPDATE_EVENT),
+        ("search_events", SEARCH_EVENTS),
+        ("overdue_events", OVERDUE_EVENTS),
+        ("event_transitions", EVENT_TRANSITIONS),
+        ("series_events", SERIES_EVENTS),
+        ("event_lineage", EVENT_LINEAGE),
+        ("due_events", DUE_EVENTS),
+        ("next_due", NEXT_DUE),
+        ("namespace_transitions", NAMESPACE_TRANSITIONS),
+    ];
 
-            let update_stmt = client
-                .prepare(include_str!("../res/db/update_event.sql"))
-                .await?;
+    /// Prepare all statements on a new connection, including one replacing a connection that was
+    /// lost, so that they are cached for the lifetime of the connection
+    pub async fn prepare(client: &ClientWrapper) -> Result<(), tokio_postgres::Error> {
+        for (_, statement) in STATEMENTS {
+            client.prepare_cached(statement).await?;
+        }
 
-            let search_stmt = client
-                .prepare(include_str!("../res/db/search_events.sql"))
-                .await?;
+        Ok(())
+    }
 
+    #[derive(Clone)]
+    pub struct EventRepoPgsql {
+        pool: Pool,
+        runtime: tokio::runtime::Handle,
+    }
+
+    impl EventRepoPgsql {
+        /// Create a repository using connections from the pool. A connection is taken right
+        /// away, so that a database that cannot be reached, or a schema that does not match the
+        /// statements, is noticed at startup rather than on the first request.
+        pub async fn new(pool: Pool) -> Result<EventRepoPgsql, RepoErr> {
             let repo = EventRepoPgsql {
-                client,
-                client_trx,
-                insert_stmt,
-                update_stmt,
-                search_stmt,
+                pool,
+                runtime: tokio::runtime::Handle::current(),
             };
 
+            repo.ping().await.map_err(RepoErr::Other)?;
+
             Ok(repo)
         }
 
-        pub async fn init(&mut self) -> Result<(), tokio_postgres::Error> {
-            self.init_enum().await?;
-            self.init_table().await?;
-            self.init_idx().await?;
+        /// Check that a working connection to the database can be had, returning why not
+        pub async fn ping(&self) -> Result<(), String> {
+            self.check(|client| async move {
+                client
+                    .simple_query("SELECT 1")
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(())
+            })
+            .await
+        }
+
+        /// Check that all tables, types, indices and triggers the service relies on exist,
+        /// returning which are missing
+        pub async fn check_schema(&self) -> Result<(), String> {
+            self.check(|client| async move {
+                let rows: Vec<Row> = client
+                    .query(include_str!("../res/db/missing_schema.sql"), &[])
+                    .await
+                    .map_err(|e| e.to_string())?;
+
+                let missing: Vec<String> = rows
+                    .iter()
+                    .map(|row| row.try_get(0))
+                    .collect::<Result<Vec<String>, tokio_postgres::Error>>()
+                    .map_err(|e| e.to_string())?;
+
+                match missing.is_empty() {
+                    true => Ok(()),
+                    false => Err(format!("Missing {}", missing.join(", "))),
+                }
+            })
+            .await
+        }
+
+        /// Check that all statements can still be prepared against the current schema, rather
+        /// than relying on the statements already prepared on each connection
+        pub async fn check_statements(&self) -> Result<(), String> {
+            self.check(|client| async move {
+                for (name, statement) in STATEMENTS {
+                    client
+                        .prepare(statement)
+                        .await
+                        .map_err(|e| format!("Unable to prepare {}: {}", name, e))?;
+                }
+                Ok(())
+            })
+            .await
+        }
+
+        /// Run a check on a connection from the pool, on the Tokio runtime like
+        /// [`EventRepoPgsql::client`], returning why it failed
+        async fn check<F, C>(&self, check: F) -> Result<(), String>
+        where
+            F: FnOnce(Object) -> C + Send + 'static,
+            C: Future<Output = Result<(), String>> + Send,
+        {
+            let pool: Pool = self.pool.clone();
+            let checked = self.runtime.spawn(async move {
+                let client: Object = pool.get().await.map_err(|e| e.to_string())?;
+                check(client).await
+            });
+
+            checked.await.map_err(|e| e.to_string())?
+        }
+
+        /// Take a connection from the pool, waiting for one to be returned if all are in use.
+        /// This is done on the Tokio runtime, where new connections must be created, since
+        /// requests may be served by another executor.
+        async fn client(&self) -> Result<Object, RepoErr> {
+            let pool: Pool = self.pool.clone();
+            let start = Instant::now();
+            let client = self
+                .runtime
+                .spawn(async move { pool.get().await }.in_current_span())
+                .await;
+            metrics::observe_pool_wait(start.elapsed());
+
+            match client {
+                Ok(res) => res.map_err(RepoErr::from),
+                Err(e) => Err(RepoErr::Other(e.to_string())),
+            }
+        }
+
+        /// Create or migrate the database schema. This must be done before the repository is
+        /// created, since statements cannot be prepared against columns that do not exist yet.
+        pub async fn init(client: &tokio_postgres::Client) -> Result<(), tokio_postgres::Error> {
+            Self::init_enum(client).await?;
+            Self::init_table(client).await?;
+            Self::init_idx(client).await?;
+            Self::init_transitions(client).await?;
+            Self::init_retention(client).await?;
+            Self::init_triggers(client).await?;
 
             Ok(())
         }
 
-        async fn init_enum(&self) -> Result<(), tokio_postgres::Error> {
-            let rows: Vec<Row> = self.client
+        async fn init_enum(client: &tokio_postgres::Client) -> Result<(), tokio_postgres::Error> {
+            let rows: Vec<Row> = client
             .query(
                 "SELECT * FROM pg_enum WHERE enumlabel IN ('SCHEDULED', 'DISABLED', 'COMPLETED')",
-                &vec![],
+                &[],
             )
             .await?;
 
             match rows.len() {
                 3 => Ok(()),
-                0 => self
-                    .client
+                0 => client
                     .simple_query(include_str!("../res/db/create_state_enums.sql"))
                     .await
                     .map(|_| ()),
@@ -78,72 +200,280 @@ pub mod event {
             }
         }
 
-        async fn init_table(&self) -> Result<(), tokio_postgres::Error> {
-            self.client
+        async fn init_table(client: &tokio_postgres::Client) -> Result<(), tokio_postgres::Error> {
+            client
                 .simple_query(include_str!("../res/db/create_events_table.sql"))
                 .await
                 .map(|_| ())
         }
 
-        async fn init_idx(&self) -> Result<(), tokio_postgres::Error> {
-            self.client
+        async fn init_idx(client: &tokio_postgres::Client) -> Result<(), tokio_postgres::Error> {
+            client
                 .simple_query(include_str!("../res/db/create_events_indices.sql"))
                 .await
                 .map(|_| ())
         }
 
-        pub async fn search(
+        async fn init_transitions(
+            client: &tokio_postgres::Client,
+        ) -> Result<(), tokio_postgres::Error> {
+            client
+                .simple_query(include_str!("../res/db/create_event_transitions_table.sql"))
+                .await
+                .map(|_| ())
+        }
+
+        async fn init_retention(
+            client: &tokio_postgres::Client,
+        ) -> Result<(), tokio_postgres::Error> {
+            client
+                .simple_query(include_str!(
+                    "../res/db/create_retention_policies_table.sql"
+                ))
+                .await
+                .map(|_| ())
+        }
+
+        async fn init_triggers(
+            client: &tokio_postgres::Client,
+        ) -> Result<(), tokio_postgres::Error> {
+            client
+                .simple_query(include_str!("../res/db/create_event_triggers.sql"))
+                .await
+                .map(|_| ())
+        }
+
+        pub async fn search(&self, query: &SearchQuery) -> Result<Vec<Event>, RepoErr> {
+            traced("search", async {
+                let states: Vec<State> = query.state();
+                let (min, max) = query.scheduled_at();
+
+                let limit: i64 = query.limit();
+
+                let params: [&(dyn ToSql + Sync); 8] = [
+                    &query.namespace(),
+                    &query.key(),
+                    &states.first(),
+                    &states.get(1),
+                    &states.get(2),
+                    &min,
+                    &max,
+                    &limit,
+                ];
+
+                let client: Object = self.client().await?;
+                let statement: Statement = client.prepare_cached(SEARCH_EVENTS).await?;
+                let rows: Vec<Row> = client.query(&statement, &params).await?;
+
+                let events: Vec<Event> = rows
+                    .iter()
+                    .filter_map(|row| Event::try_from(row).ok())
+                    .collect();
+
+                info!(namespace = query.namespace(); "Search successful");
+
+                Ok(events)
+            })
+            .await
+        }
+
+        /// Stream all events matching the query, rather than collecting them in memory. Unlike
+        /// [`EventRepoPgsql::search`], there is no default limit on the number of events.
+        pub async fn stream(
             &self,
             query: &SearchQuery,
-        ) -> Result<Vec<Event>, tokio_postgres::Error> {
-            let states: Vec<State> = query.state();
-            let (min, max) = query.scheduled_at();
-
-            let limit: i64 = query.limit();
-
-            let params: [&(dyn ToSql + Sync); 8] = [
-                &query.namespace(),
-                &query.key(),
-                &states.get(0),
-                &states.get(1),
-                &states.get(2),
-                &min,
-                &max,
-                &limit,
-            ];
-
-            let rows: Vec<Row> = self.client.query(&self.search_stmt, &params).await?;
-
-            let events: Vec<Event> = rows
-                .iter()
-                .filter_map(|row| Event::try_from(row).ok())
-                .collect();
-
-            info!("Search successful");
-
-            Ok(events)
-        }
-
-        pub async fn insert(&self, event: CreateEvent) -> Result<Event, RepoErr> {
-            let params: [&(dyn ToSql + Sync); 4] = [
-                &event.key(),
-                &event.namespace(),
-                &event.schedule_at().unwrap(),
-                &event.value(),
-            ];
-
-            let rows: Vec<Row> = self
-                .client
-                .query(&self.insert_stmt, params.as_slice())
-                .await?;
+        ) -> Result<impl Stream<Item = Result<Event, RepoErr>>, RepoErr> {
+            traced("stream", async {
+                let states: Vec<State> = query.state();
+                let (min, max) = query.scheduled_at();
 
-            match rows.first() {
-                Some(row) => match row.try_into() {
-                    Ok(event) => Ok(event),
-                    Err(e) => Err(RepoErr::from(e)),
-                },
-                None => Err(RepoErr::NoResult),
-            }
+                let params: [&(dyn ToSql + Sync); 8] = [
+                    &query.namespace(),
+                    &query.key(),
+                    &states.first(),
+                    &states.get(1),
+                    &states.get(2),
+                    &min,
+                    &max,
+                    &query.explicit_limit(),
+                ];
+
+                let client: Object = self.client().await?;
+                let statement: Statement = client.prepare_cached(SEARCH_EVENTS).await?;
+                let rows: RowStream = client.query_raw(&statement, params.iter().copied()).await?;
+
+                // The connection is kept until the stream is dropped, rather than being handed to
+                // another request that would have to wait for all rows to be read
+                let events = rows.map(move |row| {
+                    let _client: &Object = &client;
+                    match row {
+                        Ok(row) => Event::try_from(&row).map_err(RepoErr::from),
+                        Err(e) => Err(RepoErr::from(e)),
+                    }
+                });
+
+                Ok(events)
+            })
+            .await
+        }
+
+        /// Number of events in each state of each namespace
+        pub async fn count_by_state(&self) -> Result<Vec<(String, String, i64)>, RepoErr> {
+            traced("count_by_state", async {
+                let rows: Vec<Row> = self
+                    .client()
+                    .await?
+                    .query(include_str!("../res/db/count_events.sql"), &[])
+                    .await?;
+
+                rows.iter()
+                    .map(|row| Ok((row.try_get(0)?, row.try_get(1)?, row.try_get(2)?)))
+                    .collect()
+            })
+            .await
+        }
+
+        pub async fn overdue(&self) -> Result<Vec<Overdue>, RepoErr> {
+            traced("overdue", async {
+                let client: Object = self.client().await?;
+                let statement: Statement = client.prepare_cached(OVERDUE_EVENTS).await?;
+                let rows: Vec<Row> = client.query(&statement, &[]).await?;
+
+                rows.iter()
+                    .map(|row| Overdue::try_from(row).map_err(RepoErr::from))
+                    .collect()
+            })
+            .await
+        }
+
+        /// Events in the namespace that are still scheduled and became due after the given position
+        /// but no later than `until`, ordered by when they became due. The position is the
+        /// scheduled time and id of the last event seen.
+        pub async fn due(
+            &self,
+            namespace: &str,
+            after: (chrono::DateTime<chrono::Utc>, uuid::Uuid),
+            until: chrono::DateTime<chrono::Utc>,
+            limit: i64,
+        ) -> Result<Vec<Event>, RepoErr> {
+            traced("due", async {
+                let params: [&(dyn ToSql + Sync); 5] =
+                    [&namespace, &after.0, &after.1, &until, &limit];
+                let client: Object = self.client().await?;
+                let statement: Statement = client.prepare_cached(DUE_EVENTS).await?;
+                let rows: Vec<Row> = client.query(&statement, params.as_slice()).await?;
+
+                rows.iter()
+                    .map(|row| Event::try_from(row).map_err(RepoErr::from))
+                    .collect()
+            })
+            .await
+        }
+
+        /// When the next scheduled event in the namespace after the given time becomes due, if
+        /// there is any.
+        pub async fn next_due(
+            &self,
+            namespace: &str,
+            after: chrono::DateTime<chrono::Utc>,
+        ) -> Result<Option<chrono::DateTime<chrono::Utc>>, RepoErr> {
+            traced("next_due", async {
+                let params: [&(dyn ToSql + Sync); 2] = [&namespace, &after];
+                let client: Object = self.client().await?;
+                let statement: Statement = client.prepare_cached(NEXT_DUE).await?;
+                let row: Row = client.query_one(&statement, params.as_slice()).await?;
+
+                Ok(row.try_get(0)?)
+            })
+            .await
+        }
+
+        /// State transitions of events in the namespace that were recorded after the transition
+        /// with the given sequence number, together with their own sequence number.
+        pub async fn transitions(
+            &self,
+            namespace: &str,
+            after: i64,
+            limit: i64,
+        ) -> Result<Vec<(i64, Transition)>, RepoErr> {
+            traced("transitions", async {
+                let params: [&(dyn ToSql + Sync); 3] = [&namespace, &after, &limit];
+                let client: Object = self.client().await?;
+                let statement: Statement = client.prepare_cached(NAMESPACE_TRANSITIONS).await?;
+                let rows: Vec<Row> = client.query(&statement, params.as_slice()).await?;
+
+                rows.iter()
+                    .map(|row| Ok((row.try_get(5)?, Transition::try_from(row)?)))
+                    .collect()
+            })
+            .await
+        }
+
+        /// Sequence number of the latest recorded state transition, or 0 if there is none.
+        pub async fn last_transition(&self) -> Result<i64, RepoErr> {
+            traced("last_transition", async {
+                let row: Row = self
+                    .client()
+                    .await?
+                    .query_one("SELECT COALESCE(MAX(id), 0) FROM event_transitions", &[])
+                    .await?;
+
+                Ok(row.try_get(0)?)
+            })
+            .await
+        }
+
+        pub async fn insert(
+            &self,
+            event: CreateEvent,
+            caller: Option<&str>,
+        ) -> Result<Event, RepoErr> {
+            let scheduled: Event = traced("insert", async {
+                if event.conflict() != Conflict::Reject {
+                    let mut client: Object = self.client().await?;
+                    let trx: Transaction = client.transaction().await?;
+                    let event: Event = insert(&trx, &event, caller).await?;
+                    trx.commit().await?;
+                    return Ok(event);
+                }
+
+                let params: [&(dyn ToSql + Sync); 6] = [
+                    &event.key(),
+                    &event.namespace(),
+                    &event.schedule_at().unwrap(),
+                    &event.value(),
+                    &event.idempotence_key(),
+                    &None::<uuid::Uuid>,
+                ];
+
+                let client: Object = self.client().await?;
+                let statement: Statement = client.prepare_cached(INSERT_EVENT).await?;
+                let rows: Vec<Row> = client.query(&statement, params.as_slice()).await?;
+
+                match rows.first() {
+                    Some(row) => match row.try_into() {
+                        Ok(event) => Ok(event),
+                        Err(e) => Err(RepoErr::from(e)),
+                    },
+                    None => {
+                        replayed(
+                            &**client,
+                            event.key(),
+                            event.namespace(),
+                            event.idempotence_key(),
+                        )
+                        .await
+                    }
+                }
+            })
+            .await?;
+
+            debug!(
+                namespace = scheduled.namespace(), key = scheduled.key(), event_id:% = scheduled.id();
+                "Scheduled event"
+            );
+
+            Ok(scheduled)
         }
 
         pub async fn get(
@@ -152,106 +482,812 @@ pub mod event {
             id: uuid::Uuid,
             namespace: &str,
         ) -> Result<Option<Event>, RepoErr> {
-            let params: [&(dyn ToSql + Sync); 3] = [&key, &id, &namespace];
+            traced("get", async {
+                let params: [&(dyn ToSql + Sync); 3] = [&key, &id, &namespace];
 
-            let rows: Vec<Row> = self
-                .client
-                .query(
-                    "SELECT * FROM events WHERE key = $1 AND id = $2 AND namespace = $3",
-                    params.as_slice(),
-                )
-                .await?;
+                let rows: Vec<Row> = self
+                    .client()
+                    .await?
+                    .query(
+                        "SELECT * FROM events WHERE key = $1 AND id = $2 AND namespace = $3",
+                        params.as_slice(),
+                    )
+                    .await?;
 
-            match rows.first() {
-                Some(row) => match Event::try_from(row) {
-                    Ok(event) => Ok(Some(event)),
-                    Err(e) => Err(RepoErr::from(e)),
-                },
-                None => Ok(None),
-            }
+                match rows.first() {
+                    Some(row) => match Event::try_from(row) {
+                        Ok(event) => Ok(Some(event)),
+                        Err(e) => Err(RepoErr::from(e)),
+                    },
+                    None => Ok(None),
+                }
+            })
+            .await
         }
 
-        pub async fn change_state(&self, update: &SettleEvent) -> Result<Option<Event>, RepoErr> {
-            if let State::Scheduled = update.state {
-                return Err(RepoErr::IllegalState);
-            }
+        /// Settle an event. If `version` is given, the event is only updated if it still is at
+        /// that version. The change is recorded in the history of the event, attributed to
+        /// `caller`.
+        pub async fn change_state(
+            &self,
+            update: &SettleEvent,
+            version: Option<i64>,
+            caller: Option<&str>,
+        ) -> Result<Option<Event>, RepoErr> {
+            let settled: Option<Event> = traced("change_state", async {
+                if let State::Scheduled = update.state {
+                    return Err(RepoErr::IllegalState);
+                }
 
-            let SettleEvent {
-                key,
-                id,
-                namespace,
-                state,
-            } = update;
+                let SettleEvent {
+                    key,
+                    id,
+                    namespace,
+                    state,
+                } = update;
 
-            let params: [&(dyn ToSql + Sync); 4] = [&state, &id, &key, &namespace];
+                let params: [&(dyn ToSql + Sync); 6] =
+                    [&state, &id, &key, &namespace, &version, &caller];
 
-            let rows: Vec<Row> = self
-                .client
-                .query(&self.update_stmt, params.as_slice())
-                .await?;
+                let client: Object = self.client().await?;
+                let statement: Statement = client.prepare_cached(UPDATE_EVENT).await?;
+                let rows: Vec<Row> = client.query(&statement, params.as_slice()).await?;
+
+                match rows.first() {
+                    Some(row) => match Event::try_from(row) {
+                        Ok(event) => Ok(Some(event)),
+                        Err(e) => Err(RepoErr::from(e)),
+                    },
+                    None => Ok(None),
+                }
+            })
+            .await?;
 
-            match rows.first() {
-                Some(row) => match Event::try_from(row) {
-                    Ok(event) => Ok(Some(event)),
-                    Err(e) => Err(RepoErr::from(e)),
-                },
-                None => Ok(None),
+            if let Some(event) = &settled {
+                debug!(
+                    namespace = event.namespace(), key = event.key(), event_id:% = event.id();
+                    "Settled event as {:?}", update.state
+                );
             }
+
+            Ok(settled)
         }
 
         pub async fn update_and_insert(
             &self,
             replace: &SettleAndNextEvent,
+            version: Option<i64>,
+            caller: Option<&str>,
         ) -> Result<Event, RepoErr> {
-            if let State::Scheduled = replace.state {
-                return Err(RepoErr::IllegalState);
+            let next: Event = traced("update_and_insert", async {
+                if let State::Scheduled = replace.state {
+                    return Err(RepoErr::IllegalState);
+                }
+
+                let mut client: Object = self.client().await?;
+
+                let trx: Transaction = client.transaction().await?;
+                let event: Event = settle_and_insert(&trx, replace, version, caller).await?;
+                trx.commit().await?;
+
+                Ok(event)
+            })
+            .await?;
+
+            debug!(
+                namespace = next.namespace(), key = next.key(), event_id:% = next.id();
+                "Settled event as {:?} and scheduled next event", replace.state
+            );
+
+            Ok(next)
+        }
+
+        /// Apply all operations in a single transaction. In [`BatchMode::Atomic`] the first
+        /// failing operation rolls back the whole batch, while in [`BatchMode::Partial`] each
+        /// operation runs in its own savepoint so that only the failing operations are discarded.
+        pub async fn batch(
+            &self,
+            ops: &[BatchOp<'_>],
+            mode: BatchMode,
+            caller: Option<&str>,
+        ) -> Result<Vec<BatchResult>, RepoErr> {
+            traced("batch", async {
+                let mut client: Object = self.client().await?;
+                let mut trx: Transaction = client.transaction().await?;
+                let mut results: Vec<BatchResult> = Vec::with_capacity(ops.len());
+
+                for op in ops {
+                    let res: BatchResult = match mode {
+                        BatchMode::Atomic => apply(&trx, op, caller).await,
+                        BatchMode::Partial => apply_savepoint(&mut trx, op, caller).await?,
+                    };
+
+                    let failed: bool = res.is_err();
+                    results.push(res);
+
+                    if failed && matches!(mode, BatchMode::Atomic) {
+                        trx.rollback().await?;
+                        return Ok(results);
+                    }
+                }
+
+                trx.commit().await?;
+
+                Ok(results)
+            })
+            .await
+        }
+
+        /// Apply operations as in a partial batch, but roll everything back afterwards, so that
+        /// the result of each operation can be previewed without changing anything.
+        pub async fn preview_batch(
+            &self,
+            ops: &[BatchOp<'_>],
+            caller: Option<&str>,
+        ) -> Result<Vec<BatchResult>, RepoErr> {
+            traced("preview_batch", async {
+                let mut client: Object = self.client().await?;
+                let mut trx: Transaction = client.transaction().await?;
+                let mut results: Vec<BatchResult> = Vec::with_capacity(ops.len());
+
+                for op in ops {
+                    results.push(apply_savepoint(&mut trx, op, caller).await?);
+                }
+
+                trx.rollback().await?;
+
+                Ok(results)
+            })
+            .await
+        }
+
+        /// All state transitions of an event, oldest first, or `None` if there is no such event
+        /// in the namespace.
+        pub async fn history(
+            &self,
+            namespace: &str,
+            id: uuid::Uuid,
+        ) -> Result<Option<Vec<Transition>>, RepoErr> {
+            traced("history", async {
+                let params: [&(dyn ToSql + Sync); 2] = [&id, &namespace];
+                let exists: Vec<Row> = self
+                    .client()
+                    .await?
+                    .query(
+                        "SELECT id FROM events WHERE id = $1 AND namespace = $2",
+                        params.as_slice(),
+                    )
+                    .await?;
+
+                if exists.is_empty() {
+                    return Ok(None);
+                }
+
+                let params: [&(dyn ToSql + Sync); 2] = [&namespace, &id];
+                let client: Object = self.client().await?;
+                let statement: Statement = client.prepare_cached(EVENT_TRANSITIONS).await?;
+                let rows: Vec<Row> = client.query(&statement, params.as_slice()).await?;
+
+                rows.iter()
+                    .map(|row| Transition::try_from(row).map_err(RepoErr::from))
+                    .collect::<Result<Vec<Transition>, RepoErr>>()
+                    .map(Some)
+            })
+            .await
+        }
+
+        /// All events in a series, in the order they were created.
+        pub async fn series(
+            &self,
+            namespace: &str,
+            series_id: uuid::Uuid,
+        ) -> Result<Vec<Event>, RepoErr> {
+            traced("series", async {
+                let params: [&(dyn ToSql + Sync); 2] = [&namespace, &series_id];
+                let client: Object = self.client().await?;
+                let statement: Statement = client.prepare_cached(SERIES_EVENTS).await?;
+                let rows: Vec<Row> = client.query(&statement, params.as_slice()).await?;
+
+                rows.iter()
+                    .map(|row| Event::try_from(row).map_err(RepoErr::from))
+                    .collect()
+            })
+            .await
+        }
+
+        /// The event with the given id followed by each of its predecessors, newest first.
+        pub async fn lineage(
+            &self,
+            namespace: &str,
+            id: uuid::Uuid,
+        ) -> Result<Vec<Event>, RepoErr> {
+            traced("lineage", async {
+                let params: [&(dyn ToSql + Sync); 2] = [&namespace, &id];
+                let client: Object = self.client().await?;
+                let statement: Statement = client.prepare_cached(EVENT_LINEAGE).await?;
+                let rows: Vec<Row> = client.query(&statement, params.as_slice()).await?;
+
+                rows.iter()
+                    .map(|row| Event::try_from(row).map_err(RepoErr::from))
+                    .collect()
+            })
+            .await
+        }
+
+        pub async fn retention_policies(&self) -> Result<Vec<RetentionPolicy>, RepoErr> {
+            traced("retention_policies", async {
+                let rows: Vec<Row> = self
+                    .client()
+                    .await?
+                    .query("SELECT * FROM retention_policies ORDER BY namespace", &[])
+                    .await?;
+
+                rows.iter()
+                    .map(|row| RetentionPolicy::try_from(row).map_err(RepoErr::from))
+                    .collect()
+            })
+            .await
+        }
+
+        pub async fn retention_policy(
+            &self,
+            namespace: &str,
+        ) -> Result<Option<RetentionPolicy>, RepoErr> {
+            traced("retention_policy", async {
+                let rows: Vec<Row> = self
+                    .client()
+                    .await?
+                    .query(
+                        "SELECT * FROM retention_policies WHERE namespace = $1",
+                        &[&namespace],
+                    )
+                    .await?;
+
+                match rows.first() {
+                    Some(row) => Ok(Some(RetentionPolicy::try_from(row)?)),
+                    None => Ok(None),
+                }
+            })
+            .await
+        }
+
+        pub async fn set_retention_policy(
+            &self,
+            policy: &RetentionPolicy,
+        ) -> Result<RetentionPolicy, RepoErr> {
+            traced("set_retention_policy", async {
+                let params: [&(dyn ToSql + Sync); 3] = [
+                    &policy.namespace(),
+                    &policy.max_age_seconds(),
+                    &policy.max_count(),
+                ];
+
+                let rows: Vec<Row> = self
+                    .client()
+                    .await?
+                    .query(
+                        "INSERT INTO retention_policies(namespace, max_age_seconds, max_count)
+                        VALUES($1, $2, $3)
+                        ON CONFLICT (namespace)
+                        DO UPDATE SET max_age_seconds = $2, max_count = $3
+                        RETURNING *",
+                        params.as_slice(),
+                    )
+                    .await?;
+
+                match rows.first() {
+                    Some(row) => Ok(RetentionPolicy::try_from(row)?),
+                    None => Err(RepoErr::NoResult),
+                }
+            })
+            .await
+        }
+
+        pub async fn delete_retention_policy(&self, namespace: &str) -> Result<bool, RepoErr> {
+            traced("delete_retention_policy", async {
+                let deleted: u64 = self
+                    .client()
+                    .await?
+                    .execute(
+                        "DELETE FROM retention_policies WHERE namespace = $1",
+                        &[&namespace],
+                    )
+                    .await?;
+
+                Ok(deleted > 0)
+            })
+            .await
+        }
+
+        /// Number of expired events per namespace, optionally limited to a single namespace.
+        pub async fn expired(
+            &self,
+            namespace: Option<&str>,
+        ) -> Result<BTreeMap<String, i64>, RepoErr> {
+            traced("expired", async {
+                let rows: Vec<Row> = self
+                    .client()
+                    .await?
+                    .query(include_str!("../res/db/expired_events.sql"), &[&namespace])
+                    .await?;
+
+                rows.iter()
+                    .map(|row| Ok((row.try_get(0)?, row.try_get(1)?)))
+                    .collect()
+            })
+            .await
+        }
+
+        /// Delete at most `limit` expired events, returning the namespace of each deleted event.
+        pub async fn purge_expired(
+            &self,
+            namespace: Option<&str>,
+            limit: i64,
+        ) -> Result<Vec<String>, RepoErr> {
+            traced("purge_expired", async {
+                let params: [&(dyn ToSql + Sync); 2] = [&namespace, &limit];
+                let rows: Vec<Row> = self
+                    .client()
+                    .await?
+                    .query(
+                        include_str!("../res/db/purge_events.sql"),
+                        params.as_slice(),
+                    )
+                    .await?;
+
+                rows.iter()
+                    .map(|row| row.try_get(0).map_err(RepoErr::from))
+                    .collect()
+            })
+            .await
+        }
+
+        /// Remove at most `limit` finished events scheduled before `before` from the database,
+        /// after they have been handed to `write`. The events are only deleted if `write`
+        /// succeeds. Returns the number of removed events.
+        pub async fn archive_finished<F>(
+            &self,
+            before: chrono::DateTime<chrono::Utc>,
+            namespace: Option<&str>,
+            limit: i64,
+            write: F,
+        ) -> Result<usize, RepoErr>
+        where
+            F: FnOnce(&[ArchivedEvent]) -> std::io::Result<()>,
+        {
+            traced("archive_finished", async {
+                let mut client: Object = self.client().await?;
+                let trx: Transaction = client.transaction().await?;
+
+                let params: [&(dyn ToSql + Sync); 3] = [&before, &namespace, &limit];
+                let rows: Vec<Row> = trx
+                    .query(
+                        include_str!("../res/db/finished_events.sql"),
+                        params.as_slice(),
+                    )
+                    .await?;
+
+                let events: Vec<Event> = rows
+                    .iter()
+                    .map(Event::try_from)
+                    .collect::<Result<Vec<Event>, tokio_postgres::Error>>()?;
+
+                if events.is_empty() {
+                    return Ok(0);
+                }
+
+                // Transitions are deleted along with their events, so they are archived as well
+                let ids: Vec<uuid::Uuid> = events.iter().map(|event| event.id()).collect();
+                let rows: Vec<Row> = trx
+                    .query(include_str!("../res/db/archived_transitions.sql"), &[&ids])
+                    .await?;
+
+                let mut transitions: BTreeMap<uuid::Uuid, Vec<Transition>> = BTreeMap::new();
+                for row in &rows {
+                    let transition = Transition::try_from(row)?;
+                    transitions
+                        .entry(transition.event_id())
+                        .or_default()
+                        .push(transition);
+                }
+
+                let archived: Vec<ArchivedEvent> = events
+                    .into_iter()
+                    .map(|event| {
+                        let history: Vec<Transition> =
+                            transitions.remove(&event.id()).unwrap_or_default();
+                        ArchivedEvent::new(event, history)
+                    })
+                    .collect();
+
+                if let Err(e) = write(&archived) {
+                    trx.rollback().await?;
+                    return Err(RepoErr::Other(e.to_string()));
+                }
+
+                trx.execute("DELETE FROM events WHERE id = ANY($1)", &[&ids])
+                    .await?;
+                trx.commit().await?;
+
+                Ok(archived.len())
+            })
+            .await
+        }
+
+        /// Insert an event exactly as it is, including its id and timestamps, along with the
+        /// transitions it was archived with. Returns false if an event with the same id already
+        /// exists, in which case nothing is changed.
+        pub async fn restore(&self, archived: &ArchivedEvent) -> Result<bool, RepoErr> {
+            traced("restore", async {
+                let event: &Event = archived.event();
+                let params: [&(dyn ToSql + Sync); 11] = [
+                    &event.id(),
+                    &event.key(),
+                    &event.value(),
+                    &event.idempotence_key(),
+                    &event.namespace(),
+                    &event.state(),
+                    event.created_at(),
+                    event.schedule_at(),
+                    &event.version(),
+                    &event.previous_id(),
+                    &event.series_id(),
+                ];
+
+                let mut client: Object = self.client().await?;
+                let trx: Transaction = client.transaction().await?;
+
+                let rows: Vec<Row> = trx
+                    .query(
+                        include_str!("../res/db/restore_event.sql"),
+                        params.as_slice(),
+                    )
+                    .await?;
+
+                if rows.is_empty() {
+                    return Ok(false);
+                }
+
+                for transition in archived.transitions() {
+                    let params: [&(dyn ToSql + Sync); 5] = [
+                        &event.id(),
+                        &transition.old_state(),
+                        &transition.new_state(),
+                        transition.changed_at(),
+                        &transition.changed_by(),
+                    ];
+                    trx.execute(
+                        include_str!("../res/db/restore_transition.sql"),
+                        params.as_slice(),
+                    )
+                    .await?;
+                }
+
+                trx.commit().await?;
+
+                Ok(true)
+            })
+            .await
+        }
+    }
+
+    pub enum BatchOp<'a> {
+        Create(&'a CreateEvent),
+        Settle(&'a SettleEvent),
+        SettleAndNext(&'a SettleAndNextEvent),
+    }
+
+    pub type BatchResult = Result<Event, RepoErr>;
+
+    async fn apply(trx: &Transaction<'_>, op: &BatchOp<'_>, caller: Option<&str>) -> BatchResult {
+        match op {
+            BatchOp::Create(event) => insert(trx, event, caller).await,
+            BatchOp::Settle(update) => settle(trx, update, caller).await,
+            BatchOp::SettleAndNext(replace) => {
+                if let State::Scheduled = replace.state {
+                    return Err(RepoErr::IllegalState);
+                }
+                settle_and_insert(trx, replace, None, caller).await
             }
+        }
+    }
+
+    /// Apply an operation within a savepoint, which is rolled back if the operation fails, so that
+    /// the transaction can go on with the next operation.
+    async fn apply_savepoint(
+        trx: &mut Transaction<'_>,
+        op: &BatchOp<'_>,
+        caller: Option<&str>,
+    ) -> Result<BatchResult, RepoErr> {
+        let savepoint: Transaction = trx.savepoint("batch_item").await?;
+        let res: BatchResult = apply(&savepoint, op, caller).await;
+        match res {
+            Ok(_) => savepoint.commit().await?,
+            Err(_) => savepoint.rollback().await?,
+        }
+
+        Ok(res)
+    }
+
+    async fn insert(
+        trx: &Transaction<'_>,
+        event: &CreateEvent,
+        caller: Option<&str>,
+    ) -> Result<Event, RepoErr> {
+        let replay: Option<Event> = original(
+            &**trx,
+            event.key(),
+            event.namespace(),
+            event.idempotence_key(),
+        )
+        .await?;
+        if let Some(original) = replay {
+            return Ok(original);
+        }
+
+        if event.conflict() != Conflict::Reject {
+            if let Some(existing) = resolve_conflict(trx, event, caller).await? {
+                return Ok(existing);
+            }
+        }
+
+        let params: [&(dyn ToSql + Sync); 6] = [
+            &event.key(),
+            &event.namespace(),
+            &event.schedule_at().unwrap(),
+            &event.value(),
+            &event.idempotence_key(),
+            &None::<uuid::Uuid>,
+        ];
+
+        let rows: Vec<Row> = trx
+            .query(
+                include_str!("../res/db/insert_event.sql"),
+                params.as_slice(),
+            )
+            .await?;
+
+        match rows.first() {
+            Some(row) => match Event::try_from(row) {
+                Ok(event) => Ok(event),
+                Err(e) => Err(RepoErr::from(e)),
+            },
+            None => {
+                replayed(
+                    &**trx,
+                    event.key(),
+                    event.namespace(),
+                    event.idempotence_key(),
+                )
+                .await
+            }
+        }
+    }
+
+    /// Resolve a conflict with an already scheduled event for the same namespace and key, according
+    /// to the conflict mode of the new event. Returns the existing event if it should be kept, or
+    /// disables it and returns `None` if the new event should be inserted in its place. Inserts for
+    /// the same namespace and key are serialised until the end of the transaction, since there may
+    /// not be a scheduled event to lock yet, or it may be replaced before the lock is granted.
+    async fn resolve_conflict(
+        trx: &Transaction<'_>,
+        event: &CreateEvent,
+        caller: Option<&str>,
+    ) -> Result<Option<Event>, RepoErr> {
+        let params: [&(dyn ToSql + Sync); 2] = [&event.namespace(), &event.key()];
+        trx.execute(
+            "SELECT pg_advisory_xact_lock(hashtext($1 || '/' || $2))",
+            params.as_slice(),
+        )
+        .await?;
+
+        let rows: Vec<Row> = trx
+            .query(
+                "SELECT * FROM events WHERE namespace = $1 AND key = $2 AND state = 'SCHEDULED' FOR UPDATE",
+                params.as_slice(),
+            )
+            .await?;
 
-            let SettleAndNextEvent {
-                key,
-                id,
-                namespace,
-                state,
-                next,
-            } = replace;
+        let existing: Event = match rows.first() {
+            Some(row) => Event::try_from(row)?,
+            None => return Ok(None),
+        };
 
-            let params: [&(dyn ToSql + Sync); 4] = [&state, &id, &key, &namespace];
+        if Some(existing.idempotence_key()) == event.idempotence_key() {
+            return Ok(Some(existing));
+        }
+
+        let schedule_at: chrono::DateTime<chrono::Utc> = event.schedule_at().unwrap();
+        let keep: bool = match event.conflict() {
+            Conflict::Reject => return Err(RepoErr::AlreadyScheduled),
+            Conflict::Replace => false,
+            Conflict::KeepEarliest => existing.schedule_at() <= &schedule_at,
+            Conflict::KeepLatest => existing.schedule_at() >= &schedule_at,
+        };
+
+        if keep {
+            return Ok(Some(existing));
+        }
+
+        let params: [&(dyn ToSql + Sync); 2] = [&existing.id(), &caller];
+        trx.execute(
+            include_str!("../res/db/disable_event.sql"),
+            params.as_slice(),
+        )
+        .await?;
 
-            let mut client_trx = self.client_trx.lock().await;
+        Ok(None)
+    }
+
+    /// Settle an event, or return it as it is if it could not be updated because it already was
+    /// completed.
+    async fn settle(
+        trx: &Transaction<'_>,
+        update: &SettleEvent,
+        caller: Option<&str>,
+    ) -> Result<Event, RepoErr> {
+        if let State::Scheduled = update.state {
+            return Err(RepoErr::IllegalState);
+        }
 
-            let trx: Transaction = client_trx.transaction().await?;
+        let SettleEvent {
+            key,
+            id,
+            namespace,
+            state,
+        } = update;
 
-            trx.query(
+        let params: [&(dyn ToSql + Sync); 6] =
+            [&state, &id, &key, &namespace, &None::<i64>, &caller];
+
+        let rows: Vec<Row> = trx
+            .query(
                 include_str!("../res/db/update_event.sql"),
                 params.as_slice(),
             )
             .await?;
 
-            let params: [&(dyn ToSql + Sync); 4] = [
-                &key,
-                &namespace,
-                &next.schedule_at().unwrap(),
-                &next.value(),
-            ];
+        let rows: Vec<Row> = match rows.is_empty() {
+            false => rows,
+            true => {
+                let params: [&(dyn ToSql + Sync); 3] = [&key, &id, &namespace];
+                trx.query(
+                    "SELECT * FROM events WHERE key = $1 AND id = $2 AND namespace = $3",
+                    params.as_slice(),
+                )
+                .await?
+            }
+        };
 
+        match rows.first() {
+            Some(row) => match Event::try_from(row) {
+                Ok(event) => Ok(event),
+                Err(e) => Err(RepoErr::from(e)),
+            },
+            None => Err(RepoErr::NoResult),
+        }
+    }
+
+    async fn settle_and_insert(
+        trx: &Transaction<'_>,
+        replace: &SettleAndNextEvent,
+        version: Option<i64>,
+        caller: Option<&str>,
+    ) -> Result<Event, RepoErr> {
+        let SettleAndNextEvent {
+            key,
+            id,
+            namespace,
+            state,
+            next,
+        } = replace;
+
+        if let Some(original) = original(&**trx, key, namespace, next.idempotence_key()).await? {
+            return Ok(original);
+        }
+
+        let params: [&(dyn ToSql + Sync); 6] = [&state, &id, &key, &namespace, &version, &caller];
+
+        let updated: Vec<Row> = trx
+            .query(
+                include_str!("../res/db/update_event.sql"),
+                params.as_slice(),
+            )
+            .await?;
+
+        if let (true, Some(version)) = (updated.is_empty(), version) {
+            let params: [&(dyn ToSql + Sync); 3] = [&key, &id, &namespace];
             let rows: Vec<Row> = trx
                 .query(
-                    include_str!("../res/db/insert_event.sql"),
+                    "SELECT version FROM events WHERE key = $1 AND id = $2 AND namespace = $3",
                     params.as_slice(),
                 )
                 .await?;
 
-            trx.commit().await?;
-            drop(client_trx);
-
-            match rows.first() {
-                Some(row) => match Event::try_from(row) {
-                    Ok(event) => Ok(event),
-                    Err(e) => Err(RepoErr::from(e)),
-                },
-                None => Err(RepoErr::NoResult),
+            if let Some(row) = rows.first() {
+                let current: i64 = row.try_get(0)?;
+                if current != version {
+                    return Err(RepoErr::VersionConflict);
+                }
             }
         }
+
+        let params: [&(dyn ToSql + Sync); 6] = [
+            &key,
+            &namespace,
+            &next.schedule_at().unwrap(),
+            &next.value(),
+            &next.idempotence_key(),
+            &id,
+        ];
+
+        let rows: Vec<Row> = trx
+            .query(
+                include_str!("../res/db/insert_event.sql"),
+                params.as_slice(),
+            )
+            .await?;
+
+        match rows.first() {
+            Some(row) => match Event::try_from(row) {
+                Ok(event) => Ok(event),
+                Err(e) => Err(RepoErr::from(e)),
+            },
+            None => replayed(&**trx, key, namespace, next.idempotence_key()).await,
+        }
+    }
+
+    /// Find the event previously created with a client supplied idempotence key, when an insert
+    /// did not return any row because of a conflict on that key.
+    async fn replayed<C: GenericClient>(
+        client: &C,
+        key: &str,
+        namespace: &str,
+        idempotence_key: Option<uuid::Uuid>,
+    ) -> Result<Event, RepoErr> {
+        match original(client, key, namespace, idempotence_key).await? {
+            Some(event) => Ok(event),
+            None => Err(RepoErr::NoResult),
+        }
+    }
+
+    /// Find the event previously created with a client supplied idempotence key, if any, so that
+    /// a replayed request returns it without changing anything. A replay is only accepted if it
+    /// targets the same namespace and key as the original event.
+    async fn original<C: GenericClient>(
+        client: &C,
+        key: &str,
+        namespace: &str,
+        idempotence_key: Option<uuid::Uuid>,
+    ) -> Result<Option<Event>, RepoErr> {
+        let idempotence_key: uuid::Uuid = match idempotence_key {
+            Some(idempotence_key) => idempotence_key,
+            None => return Ok(None),
+        };
+
+        let rows: Vec<Row> = client
+            .query(
+                "SELECT * FROM events WHERE idempotence_key = $1",
+                &[&idempotence_key],
+            )
+            .await?;
+
+        let event: Event = match rows.first() {
+            Some(row) => Event::try_from(row)?,
+            None => return Ok(None),
+        };
+
+        match event.key() == key && event.namespace() == namespace {
+            true => Ok(Some(event)),
+            false => Err(RepoErr::IdempotenceConflict),
+        }
+    }
+
+    impl From<PoolError> for RepoErr {
+        fn from(e: PoolError) -> Self {
+            log::error!("Unable to get a database connection: {}", e);
+            RepoErr::Connection
+        }
     }
 
     impl From<tokio_postgres::Error> for RepoErr {
@@ -259,6 +1295,7 @@ pub mod event {
             log::error!("DB Error: {:?}", e);
             let db_err: &DbError = match e.as_db_error() {
                 Some(err) => err,
+                None if e.is_closed() => return RepoErr::Connection,
                 None => return RepoErr::Unknown,
             };
 
@@ -274,12 +1311,114 @@ pub mod event {
     pub enum RepoErr {
         Connection,
         AlreadyScheduled,
+        IdempotenceConflict,
         IllegalState,
         Conversion,
         NoResult,
+        VersionConflict,
         Other(String),
         Unknown,
     }
+
+    impl RepoErr {
+        /// Name of the variant, without any details it carries
+        pub fn variant(&self) -> &'static str {
+            match self {
+                RepoErr::Connection => "Connection",
+                RepoErr::AlreadyScheduled => "AlreadyScheduled",
+                RepoErr::IdempotenceConflict => "IdempotenceConflict",
+                RepoErr::IllegalState => "IllegalState",
+                RepoErr::Conversion => "Conversion",
+                RepoErr::NoResult => "NoResult",
+                RepoErr::VersionConflict => "VersionConflict",
+                RepoErr::Other(_) => "Other",
+                RepoErr::Unknown => "Unknown",
+            }
+        }
+    }
+
+    impl std::fmt::Display for RepoErr {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                RepoErr::Other(details) => write!(f, "{}", details),
+                e => write!(f, "{}", e.variant()),
+            }
+        }
+    }
+}
+
+pub mod pool {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use deadpool_postgres::{
+        Hook, HookError, HookErrorCause, Manager, ManagerConfig, Pool, RecyclingMethod, Runtime,
+    };
+    use native_tls::{Certificate, TlsConnector};
+    use postgres_native_tls::MakeTlsConnector;
+
+    use super::event;
+
+    /// Connector for TLS connections to the database. Whether TLS is used depends on the
+    /// `sslmode` of the connection string, which is `prefer` unless set to `disable` or
+    /// `require`. Like libpq, the server certificate is only verified when a CA certificate is
+    /// given to verify it against.
+    pub fn tls(ca_cert: Option<&Path>) -> Result<MakeTlsConnector, String> {
+        let mut builder = TlsConnector::builder();
+        match ca_cert {
+            Some(path) => {
+                let pem: Vec<u8> = std::fs::read(path)
+                    .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+                let cert = Certificate::from_pem(&pem)
+                    .map_err(|e| format!("Invalid certificate in {}: {}", path.display(), e))?;
+                builder.add_root_certificate(cert);
+            }
+            None => {
+                builder
+                    .danger_accept_invalid_certs(true)
+                    .danger_accept_invalid_hostnames(true);
+            }
+        }
+
+        let connector: TlsConnector = builder.build().map_err(|e| e.to_string())?;
+        Ok(MakeTlsConnector::new(connector))
+    }
+
+    /// Pool of at most `size` connections to the database. Each connection is checked with a
+    /// query before it is handed out again, and connections that are broken are replaced with
+    /// new ones, on which the statements are prepared again. Waiting for, creating or checking a
+    /// connection gives up after `timeout`.
+    pub fn create(
+        url: &str,
+        size: usize,
+        timeout: Duration,
+        tls: MakeTlsConnector,
+    ) -> Result<Pool, String> {
+        let config: tokio_postgres::Config = url.parse().map_err(|e| format!("{}", e))?;
+        let manager = Manager::from_config(
+            config,
+            tls,
+            ManagerConfig {
+                recycling_method: RecyclingMethod::Verified,
+            },
+        );
+
+        Pool::builder(manager)
+            .max_size(size)
+            .wait_timeout(Some(timeout))
+            .create_timeout(Some(timeout))
+            .recycle_timeout(Some(timeout))
+            .post_create(Hook::async_fn(|client, _| {
+                Box::pin(async move {
+                    event::prepare(client)
+                        .await
+                        .map_err(|e| HookError::Abort(HookErrorCause::Backend(e)))
+                })
+            }))
+            .runtime(Runtime::Tokio1)
+            .build()
+            .map_err(|e| e.to_string())
+    }
 }
 
 pub mod webhook {}
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use postgres_types::{FromSql, ToSql};
 use serde_derive::{Deserialize, Serialize};
 use tokio_postgres::Row;
@@ -15,6 +17,11 @@ pub struct Event {
     created_at: chrono::DateTime<chrono::Utc>,
     #[serde(rename = "scheduledAt")]
     scheduled_at: chrono::DateTime<chrono::Utc>,
+    version: i64,
+    #[serde(rename = "previousId")]
+    previous_id: Option<uuid::Uuid>,
+    #[serde(rename = "seriesId")]
+    series_id: uuid::Uuid,
 }
 
 impl Event {
@@ -25,16 +32,20 @@ impl Event {
         value: Option<serde_json::Value>,
     ) -> Event {
         let value: serde_json::Value = value.unwrap_or(serde_json::Value::Null);
+        let id = uuid::Uuid::new_v4();
 
         Event {
             key,
             value,
-            id: uuid::Uuid::new_v4(),
+            id,
             namespace,
             idempotence_key: uuid::Uuid::new_v4(),
             state: State::Scheduled,
             created_at: chrono::Utc::now(),
             scheduled_at: schedule_at,
+            version: 1,
+            previous_id: None,
+            series_id: id,
         }
     }
 
@@ -62,10 +73,26 @@ impl Event {
         self.state
     }
 
+    pub fn created_at(&self) -> &chrono::DateTime<chrono::Utc> {
+        &self.created_at
+    }
+
     pub fn schedule_at(&self) -> &chrono::DateTime<chrono::Utc> {
         &self.scheduled_at
     }
 
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    pub fn previous_id(&self) -> Option<uuid::Uuid> {
+        self.previous_id
+    }
+
+    pub fn series_id(&self) -> uuid::Uuid {
+        self.series_id
+    }
+
     pub fn next(
         self,
         schedule_at: chrono::DateTime<chrono::Utc>,
@@ -81,6 +108,9 @@ impl Event {
             state: State::Scheduled,
             created_at: chrono::Utc::now(),
             scheduled_at: schedule_at,
+            version: 1,
+            previous_id: Some(self.id),
+            series_id: self.series_id,
             value,
         };
 
@@ -97,10 +127,7 @@ impl Event {
     }
 
     pub fn is_scheduled(&self) -> bool {
-        match self.state {
-            State::Scheduled => true,
-            _ => false,
-        }
+        matches!(self.state, State::Scheduled)
     }
 
     pub fn disable(self) -> Event {
@@ -118,7 +145,11 @@ impl Event {
     }
 
     fn change_state(self, state: State) -> Event {
-        Event { state, ..self }
+        Event {
+            state,
+            version: self.version + 1,
+            ..self
+        }
     }
 }
 
@@ -135,12 +166,117 @@ impl TryFrom<&Row> for Event {
             state: value.try_get(5)?,
             created_at: value.try_get(6)?,
             scheduled_at: value.try_get(7)?,
+            version: value.try_get(8)?,
+            previous_id: value.try_get(9)?,
+            series_id: value.try_get(10)?,
         };
 
         Ok(event)
     }
 }
 
+#[derive(Serialize, Debug, Clone)]
+pub struct Overdue {
+    namespace: String,
+    overdue: i64,
+    #[serde(rename = "maxLagMillis")]
+    max_lag_ms: i64,
+    oldest: Event,
+}
+
+impl Overdue {
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn overdue(&self) -> i64 {
+        self.overdue
+    }
+
+    pub fn max_lag_ms(&self) -> i64 {
+        self.max_lag_ms
+    }
+}
+
+impl TryFrom<&Row> for Overdue {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        let oldest = Event::try_from(value)?;
+        let overdue = Overdue {
+            namespace: oldest.namespace.clone(),
+            overdue: value.try_get(11)?,
+            max_lag_ms: value.try_get(12)?,
+            oldest,
+        };
+
+        Ok(overdue)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Transition {
+    #[serde(rename = "eventId")]
+    event_id: uuid::Uuid,
+    #[serde(rename = "oldState")]
+    old_state: State,
+    #[serde(rename = "newState")]
+    new_state: State,
+    #[serde(rename = "changedAt")]
+    changed_at: chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "changedBy")]
+    changed_by: Option<String>,
+}
+
+impl Transition {
+    /// Longest identity of a caller that can be recorded as having made a change
+    pub const MAX_CHANGED_BY_LEN: usize = 128;
+
+    /// Whether the identity of a caller can be recorded as having made a change, and shown in the
+    /// history of an event as is
+    pub fn valid_changed_by(caller: &str) -> bool {
+        !caller.is_empty()
+            && caller.chars().count() <= Transition::MAX_CHANGED_BY_LEN
+            && caller.chars().all(|c| c.is_ascii_graphic() || c == ' ')
+    }
+
+    pub fn event_id(&self) -> uuid::Uuid {
+        self.event_id
+    }
+
+    pub fn old_state(&self) -> State {
+        self.old_state
+    }
+
+    pub fn new_state(&self) -> State {
+        self.new_state
+    }
+
+    pub fn changed_at(&self) -> &chrono::DateTime<chrono::Utc> {
+        &self.changed_at
+    }
+
+    pub fn changed_by(&self) -> Option<&str> {
+        self.changed_by.as_deref()
+    }
+}
+
+impl TryFrom<&Row> for Transition {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        let transition = Transition {
+            event_id: value.try_get(0)?,
+            old_state: value.try_get(1)?,
+            new_state: value.try_get(2)?,
+            changed_at: value.try_get(3)?,
+            changed_by: value.try_get(4)?,
+        };
+
+        Ok(transition)
+    }
+}
+
 #[derive(Serialize, Deserialize, ToSql, FromSql, Debug, Copy, Clone, PartialEq, Eq)]
 #[postgres(name = "state")]
 pub enum State {
@@ -154,3 +290,16 @@ pub enum State {
     #[postgres(name = "COMPLETED")]
     Completed,
 }
+
+impl FromStr for State {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "SCHEDULED" => Ok(State::Scheduled),
+            "DISABLED" => Ok(State::Disabled),
+            "COMPLETED" => Ok(State::Completed),
+            _ => Err(format!("Unsupported state '{}'", s)),
+        }
+    }
+}
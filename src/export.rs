@@ -0,0 +1,76 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+
+use futures::{pin_mut, StreamExt};
+
+use crate::archive::restore_events;
+use crate::config::{ExportArgs, ImportArgs};
+use crate::db::event::{EventRepoPgsql, RepoErr};
+use crate::event::Event;
+use crate::search::SearchQuery;
+
+/// Write all events matching the search options as JSON Lines, to a file or to stdout.
+pub async fn export_cmd(repo: &EventRepoPgsql, args: &ExportArgs) -> Result<(), RepoErr> {
+    let output: Box<dyn Write> = match args.output() {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => return Err(RepoErr::Other(format!("{}: {}", path.display(), e))),
+        },
+        None => Box::new(io::stdout()),
+    };
+
+    let mut output = BufWriter::new(output);
+    let query: SearchQuery = args.query();
+    let events = repo.stream(&query).await?;
+    pin_mut!(events);
+
+    let mut exported: usize = 0;
+    while let Some(event) = events.next().await {
+        let event: Event = event?;
+        if let Err(e) = write_line(&mut output, &event) {
+            return Err(RepoErr::Other(e.to_string()));
+        }
+        exported += 1;
+    }
+
+    if let Err(e) = output.flush() {
+        return Err(RepoErr::Other(e.to_string()));
+    }
+
+    log::info!("Exported {} events", exported);
+
+    Ok(())
+}
+
+fn write_line<W: Write>(output: &mut W, event: &Event) -> io::Result<()> {
+    serde_json::to_writer(&mut *output, event)?;
+    output.write_all(b"\n")
+}
+
+/// Read events as JSON Lines from a file or from stdin, and insert those that do not already
+/// exist. Importing the same events more than once is safe, since events are matched on their id.
+/// Fails if any event could not be imported, after importing all others.
+pub async fn import_cmd(repo: &EventRepoPgsql, args: &ImportArgs) -> Result<(), RepoErr> {
+    let (input, source): (Box<dyn BufRead>, String) = match args.input() {
+        Some(path) => match File::open(path) {
+            Ok(file) => (Box::new(BufReader::new(file)), path.display().to_string()),
+            Err(e) => return Err(RepoErr::Other(format!("{}: {}", path.display(), e))),
+        },
+        None => (Box::new(io::stdin().lock()), String::from("stdin")),
+    };
+
+    let (imported, skipped, failed) = restore_events(repo, input, &source).await;
+
+    println!(
+        "{}: imported {} events, skipped {} already present, {} failed",
+        source, imported, skipped, failed
+    );
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(RepoErr::Other(format!(
+            "{}: {} events failed to import",
+            source, failed
+        ))),
+    }
+}
@@ -0,0 +1,188 @@
+use futures::{stream, StreamExt};
+use log::warn;
+use postgres_native_tls::MakeTlsConnector;
+use serde_derive::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast;
+use tokio_postgres::{AsyncMessage, Connection};
+
+use crate::event::State;
+use crate::health::{self, Backoff, Readiness};
+
+/// Channel that the database notifies on, from triggers on the events table
+pub const CHANNEL: &str = "event_changes";
+
+/// Number of notices a subscriber may fall behind before it starts missing notices
+const CAPACITY: usize = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Insert,
+    Update,
+}
+
+/// An event that was inserted or that changed state, as notified by the database
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Change {
+    op: Op,
+    id: uuid::Uuid,
+    namespace: String,
+    key: String,
+    #[serde(rename = "oldState")]
+    old_state: Option<State>,
+    state: State,
+    #[serde(rename = "scheduledAt")]
+    scheduled_at: chrono::DateTime<chrono::Utc>,
+    version: i64,
+}
+
+impl Change {
+    pub fn op(&self) -> Op {
+        self.op
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn id(&self) -> uuid::Uuid {
+        self.id
+    }
+
+    pub fn old_state(&self) -> Option<State> {
+        self.old_state
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn scheduled_at(&self) -> &chrono::DateTime<chrono::Utc> {
+        &self.scheduled_at
+    }
+
+    /// Whether the change may have moved the time when the next event in the namespace is due,
+    /// which happens when an event is scheduled or stops being scheduled.
+    fn affects_next_due(&self) -> bool {
+        self.state == State::Scheduled || self.old_state == Some(State::Scheduled)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum Notice {
+    /// An event was inserted or changed state
+    #[serde(rename = "change")]
+    Change(Change),
+    /// The time when the next event in the namespace is due may have changed, so anything waiting
+    /// for it should look again.
+    #[serde(rename = "nextDue")]
+    NextDue { namespace: String },
+}
+
+impl Notice {
+    pub fn namespace(&self) -> &str {
+        match self {
+            Notice::Change(change) => change.namespace(),
+            Notice::NextDue { namespace } => namespace,
+        }
+    }
+}
+
+/// Broadcasts changes to events, as notified by the database, to everyone subscribing.
+#[derive(Clone)]
+pub struct Feed {
+    sender: broadcast::Sender<Notice>,
+}
+
+impl Feed {
+    pub fn new() -> Feed {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Feed { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Notice> {
+        self.sender.subscribe()
+    }
+
+    fn publish(&self, change: Change) {
+        let next_due: Option<Notice> = match change.affects_next_due() {
+            true => Some(Notice::NextDue {
+                namespace: change.namespace.clone(),
+            }),
+            false => None,
+        };
+
+        // Sending only fails when there are no subscribers, in which case no one is missing out
+        let _ = self.sender.send(Notice::Change(change));
+        if let Some(notice) = next_due {
+            let _ = self.sender.send(notice);
+        }
+    }
+}
+
+/// Keep a connection to the database listening on [`CHANNEL`], publishing each notification to
+/// the feed. When the connection is lost or cannot be made, a new one is made after waiting a
+/// little longer each time. Changes made while there is no connection are not published.
+pub async fn listen(url: String, tls: MakeTlsConnector, feed: Feed, readiness: Readiness) {
+    let mut backoff = Backoff::new();
+
+    loop {
+        let (client, connection) = match tokio_postgres::connect(&url, tls.clone()).await {
+            Ok(connected) => connected,
+            Err(e) => {
+                readiness.report(health::FEED, Err(e.to_string()));
+                tokio::time::sleep(backoff.next()).await;
+                continue;
+            }
+        };
+
+        let forwarding = tokio::spawn(forward(connection, feed.clone()));
+        match client.batch_execute(&format!("LISTEN {}", CHANNEL)).await {
+            Ok(()) => {
+                readiness.report(health::FEED, Ok(()));
+                backoff.reset();
+                let reason: String = forwarding
+                    .await
+                    .unwrap_or_else(|e| format!("Listener failed, {}", e));
+                readiness.report(health::FEED, Err(reason));
+            }
+            Err(e) => {
+                forwarding.abort();
+                readiness.report(health::FEED, Err(e.to_string()));
+            }
+        }
+
+        tokio::time::sleep(backoff.next()).await;
+    }
+}
+
+/// Drive a database connection that is listening on [`CHANNEL`], publishing each notification to
+/// the feed, until the connection is closed. Returns why it was closed.
+async fn forward<S, T>(mut connection: Connection<S, T>, feed: Feed) -> String
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+
+    while let Some(message) = messages.next().await {
+        match message {
+            Ok(AsyncMessage::Notification(notification)) => {
+                match serde_json::from_str::<Change>(notification.payload()) {
+                    Ok(change) => feed.publish(change),
+                    Err(e) => warn!("Invalid notification {}: {}", notification.payload(), e),
+                }
+            }
+            Ok(_) => (),
+            Err(e) => return format!("Listener connection error, {}", e),
+        }
+    }
+
+    String::from("Listener connection closed")
+}
@@ -0,0 +1,491 @@
+// Status is the error type of every gRPC handler, so helpers return it as is
+#![allow(clippy::result_large_err)]
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::TimeZone;
+use log::{debug, error, info, warn};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+
+use crate::db::event::{EventRepoPgsql, RepoErr};
+use crate::event::{Event, State, Transition};
+use crate::feed::{Change, Feed, Notice, Op};
+use crate::http::event::{Conflict, CreateEvent, NextEvent, SettleAndNextEvent, SettleEvent};
+use crate::poll::{Due, Poller};
+use crate::search::SearchQuery;
+use crate::tls::Certificates;
+use crate::trace::GrpcTraced;
+
+pub mod proto {
+    tonic::include_proto!("timetable.v1");
+}
+
+use proto::timetable_server::{Timetable, TimetableServer};
+use proto::watch_response::Kind;
+
+/// Number of messages that may be buffered for a client watching events
+const WATCH_BUFFER: usize = 256;
+
+/// The gRPC API, sharing the repository, change feed and poller with the HTTP API
+pub struct TimetableService {
+    repo: EventRepoPgsql,
+    feed: Feed,
+    poller: Poller,
+}
+
+impl TimetableService {
+    pub fn new(repo: EventRepoPgsql, feed: Feed, poller: Poller) -> TimetableService {
+        TimetableService { repo, feed, poller }
+    }
+}
+
+/// Longest time a client may take to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve the gRPC API until the process exits, over TLS if there are certificates
+pub async fn serve(addr: SocketAddr, service: TimetableService, tls: Option<Arc<Certificates>>) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Unable to serve gRPC on {}: {}", addr, e);
+            return;
+        }
+    };
+
+    info!("Serving gRPC on {}", addr);
+    let server = tonic::transport::Server::builder()
+        .add_service(GrpcTraced::new(TimetableServer::new(service)));
+    let res = match tls {
+        Some(certificates) => {
+            let acceptor = TlsAcceptor::from(Arc::new(certificates.server_config(&[b"h2"])));
+            let (sender, receiver) = mpsc::channel(WATCH_BUFFER);
+            tokio::spawn(accept_tls(listener, acceptor, sender));
+            server
+                .serve_with_incoming(ReceiverStream::new(receiver))
+                .await
+        }
+        None => {
+            server
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await
+        }
+    };
+
+    if let Err(e) = res {
+        error!("gRPC server error: {}", e);
+    }
+}
+
+/// Accept connections and hand them over once the TLS handshake is done, so that a slow client
+/// does not hold up others
+async fn accept_tls(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    sender: mpsc::Sender<std::io::Result<TlsStream<TcpStream>>>,
+) {
+    loop {
+        let stream: TcpStream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Unable to accept gRPC connection: {}", e);
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let _ = sender.send(Ok(stream)).await;
+                }
+                Ok(Err(e)) => debug!("TLS handshake failed: {}", e),
+                Err(_) => debug!("TLS handshake timed out"),
+            }
+        });
+    }
+}
+
+#[tonic::async_trait]
+impl Timetable for TimetableService {
+    async fn schedule_event(
+        &self,
+        request: Request<proto::ScheduleEventRequest>,
+    ) -> Result<Response<proto::Event>, Status> {
+        let caller: Option<String> = caller(&request)?;
+        let req = request.into_inner();
+        let conflict: Conflict = match proto::Conflict::from_i32(req.conflict) {
+            Some(proto::Conflict::Reject) => Conflict::Reject,
+            Some(proto::Conflict::Replace) => Conflict::Replace,
+            Some(proto::Conflict::KeepEarliest) => Conflict::KeepEarliest,
+            Some(proto::Conflict::KeepLatest) => Conflict::KeepLatest,
+            None => return Err(Status::invalid_argument("Invalid conflict")),
+        };
+
+        let event = CreateEvent::new(
+            req.key,
+            value(&req.value)?,
+            req.namespace,
+            timestamp(req.scheduled_at)?.ok_or_else(|| Status::invalid_argument("Missing time"))?,
+        )
+        .with_idempotence_key(uuid(&req.idempotence_key)?)
+        .with_conflict(conflict);
+
+        match self.repo.insert(event.clone(), caller.as_deref()).await {
+            Ok(event) => Ok(Response::new(event.into())),
+            Err(e) => Err(status(e, event.namespace(), Some(event.key()), None)),
+        }
+    }
+
+    async fn settle_event(
+        &self,
+        request: Request<proto::SettleEventRequest>,
+    ) -> Result<Response<proto::Event>, Status> {
+        let caller: Option<String> = caller(&request)?;
+        let req = request.into_inner();
+        let update = SettleEvent {
+            key: req.key,
+            id: uuid(&req.id)?.ok_or_else(|| Status::invalid_argument("Missing id"))?,
+            namespace: req.namespace,
+            state: state(req.state)?,
+        };
+        let version: Option<i64> = expected_version(req.expected_version);
+
+        match self
+            .repo
+            .change_state(&update, version, caller.as_deref())
+            .await
+        {
+            Ok(Some(event)) => Ok(Response::new(event.into())),
+            Ok(None) => match self
+                .repo
+                .get(&update.key, update.id, &update.namespace)
+                .await
+            {
+                Ok(Some(event)) => match version {
+                    Some(version) if version != event.version() => {
+                        Err(Status::failed_precondition("Event has been modified"))
+                    }
+                    _ => Ok(Response::new(event.into())),
+                },
+                Ok(None) => Err(Status::not_found("Event not found")),
+                Err(e) => Err(status(
+                    e,
+                    &update.namespace,
+                    Some(&update.key),
+                    Some(update.id),
+                )),
+            },
+            Err(e) => Err(status(
+                e,
+                &update.namespace,
+                Some(&update.key),
+                Some(update.id),
+            )),
+        }
+    }
+
+    async fn settle_and_next(
+        &self,
+        request: Request<proto::SettleAndNextRequest>,
+    ) -> Result<Response<proto::Event>, Status> {
+        let caller: Option<String> = caller(&request)?;
+        let req = request.into_inner();
+        let next: proto::NextEvent = req
+            .next
+            .ok_or_else(|| Status::invalid_argument("Missing next event"))?;
+
+        let settle = SettleAndNextEvent {
+            key: req.key,
+            id: uuid(&req.id)?.ok_or_else(|| Status::invalid_argument("Missing id"))?,
+            namespace: req.namespace,
+            state: state(req.state)?,
+            next: NextEvent::new(
+                timestamp(next.scheduled_at)?
+                    .ok_or_else(|| Status::invalid_argument("Missing time of next event"))?,
+                value(&next.value)?,
+                uuid(&next.idempotence_key)?,
+            ),
+        };
+        let version: Option<i64> = expected_version(req.expected_version);
+
+        match self
+            .repo
+            .update_and_insert(&settle, version, caller.as_deref())
+            .await
+        {
+            Ok(event) => Ok(Response::new(event.into())),
+            Err(e) => Err(status(
+                e,
+                &settle.namespace,
+                Some(&settle.key),
+                Some(settle.id),
+            )),
+        }
+    }
+
+    async fn search_events(
+        &self,
+        request: Request<proto::SearchEventsRequest>,
+    ) -> Result<Response<proto::SearchEventsResponse>, Status> {
+        let req = request.into_inner();
+        let states: Vec<State> = req
+            .states
+            .iter()
+            .map(|s| state(*s))
+            .collect::<Result<Vec<State>, Status>>()?;
+
+        let query = SearchQuery::new(
+            req.namespace,
+            Some(req.key).filter(|key| !key.is_empty()),
+            Some(states).filter(|states| !states.is_empty()),
+            Some(req.limit).filter(|limit| *limit > 0),
+            timestamp(req.scheduled_at_min)?,
+            timestamp(req.scheduled_at_max)?,
+        );
+
+        match self.repo.search(&query).await {
+            Ok(events) => Ok(Response::new(proto::SearchEventsResponse {
+                events: events.into_iter().map(proto::Event::from).collect(),
+            })),
+            Err(e) => Err(status(e, query.namespace(), query.key().as_deref(), None)),
+        }
+    }
+
+    type WatchStream = ReceiverStream<Result<proto::WatchResponse, Status>>;
+
+    async fn watch(
+        &self,
+        request: Request<proto::WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let req = request.into_inner();
+        let (sender, receiver) = mpsc::channel(WATCH_BUFFER);
+
+        tokio::spawn(watch_events(
+            self.feed.subscribe(),
+            self.poller.subscribe(&req.namespace),
+            req.namespace,
+            req.key_prefix,
+            sender,
+        ));
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+/// Send inserts, transitions and due events in the namespace, with keys starting with the prefix,
+/// until the client goes away. Sending waits for the client to keep up.
+async fn watch_events(
+    mut notices: broadcast::Receiver<Notice>,
+    mut due: watch::Receiver<Due>,
+    namespace: String,
+    prefix: String,
+    sender: mpsc::Sender<Result<proto::WatchResponse, Status>>,
+) {
+    let mut notified: HashSet<uuid::Uuid> = HashSet::new();
+    let mut due_changed: bool = true;
+
+    loop {
+        if due_changed {
+            let events: Due = due.borrow().clone();
+            for event in events.iter().flat_map(|events| events.iter()) {
+                if !notified.contains(&event.id()) && event.key().starts_with(&prefix) {
+                    let message = proto::WatchResponse {
+                        kind: Kind::Due as i32,
+                        event_id: event.id().to_string(),
+                        key: event.key().to_string(),
+                        old_state: proto::State::Unspecified as i32,
+                        state: proto::State::from(event.state()) as i32,
+                        scheduled_at: Some(to_timestamp(event.schedule_at())),
+                        event: Some(event.clone().into()),
+                    };
+                    if sender.send(Ok(message)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            if let Some(events) = events {
+                notified = events.iter().map(Event::id).collect();
+            }
+        }
+
+        tokio::select! {
+            notice = notices.recv() => {
+                due_changed = false;
+                let change: Change = match notice {
+                    Ok(Notice::Change(change)) => change,
+                    Ok(Notice::NextDue { .. }) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                        continue
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+
+                if change.namespace() != namespace || !change.key().starts_with(&prefix) {
+                    continue;
+                }
+
+                if sender.send(Ok(change.into())).await.is_err() {
+                    return;
+                }
+            }
+            changed = due.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                due_changed = true;
+            }
+            _ = sender.closed() => return,
+        }
+    }
+}
+
+fn caller<T>(request: &Request<T>) -> Result<Option<String>, Status> {
+    match request
+        .metadata()
+        .get("x-caller-id")
+        .map(|value| value.to_str())
+    {
+        Some(Ok(caller)) if Transition::valid_changed_by(caller) => Ok(Some(caller.to_string())),
+        Some(_) => Err(Status::invalid_argument("Invalid x-caller-id metadata")),
+        None => Ok(None),
+    }
+}
+
+/// Status for a repository error. Errors that are not caused by the request are logged, along with
+/// the event the request was for.
+fn status(e: RepoErr, namespace: &str, key: Option<&str>, event_id: Option<uuid::Uuid>) -> Status {
+    match e {
+        RepoErr::AlreadyScheduled => Status::already_exists("Event is already scheduled"),
+        RepoErr::IdempotenceConflict => {
+            Status::invalid_argument("Idempotence key is used by another event")
+        }
+        RepoErr::IllegalState => Status::failed_precondition("Illegal state"),
+        RepoErr::NoResult => Status::not_found("Event not found"),
+        RepoErr::VersionConflict => Status::failed_precondition("Event has been modified"),
+        RepoErr::Connection => Status::unavailable("Database is unavailable"),
+        e => {
+            error!(
+                namespace = namespace, key = key, event_id = event_id.map(|id| id.to_string());
+                "Repository error, {:?}", e
+            );
+            Status::internal("Internal Server Error")
+        }
+    }
+}
+
+fn expected_version(version: i64) -> Option<i64> {
+    match version {
+        0 => None,
+        version => Some(version),
+    }
+}
+
+fn value(value: &str) -> Result<serde_json::Value, Status> {
+    match value.is_empty() {
+        true => Ok(serde_json::Value::Null),
+        false => serde_json::from_str(value)
+            .map_err(|e| Status::invalid_argument(format!("Invalid value, {}", e))),
+    }
+}
+
+fn uuid(id: &str) -> Result<Option<uuid::Uuid>, Status> {
+    match id.is_empty() {
+        true => Ok(None),
+        false => match id.parse() {
+            Ok(id) => Ok(Some(id)),
+            Err(_) => Err(Status::invalid_argument(format!("Invalid id {}", id))),
+        },
+    }
+}
+
+fn state(state: i32) -> Result<State, Status> {
+    match proto::State::from_i32(state) {
+        Some(proto::State::Scheduled) => Ok(State::Scheduled),
+        Some(proto::State::Disabled) => Ok(State::Disabled),
+        Some(proto::State::Completed) => Ok(State::Completed),
+        _ => Err(Status::invalid_argument("Invalid state")),
+    }
+}
+
+fn timestamp(
+    timestamp: Option<prost_types::Timestamp>,
+) -> Result<Option<chrono::DateTime<chrono::Utc>>, Status> {
+    match timestamp {
+        Some(timestamp) => match u32::try_from(timestamp.nanos) {
+            Ok(nanos) => match chrono::Utc.timestamp_opt(timestamp.seconds, nanos).single() {
+                Some(time) => Ok(Some(time)),
+                None => Err(Status::invalid_argument("Invalid timestamp")),
+            },
+            Err(_) => Err(Status::invalid_argument("Invalid timestamp")),
+        },
+        None => Ok(None),
+    }
+}
+
+fn to_timestamp(time: &chrono::DateTime<chrono::Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
+
+impl From<State> for proto::State {
+    fn from(state: State) -> Self {
+        match state {
+            State::Scheduled => proto::State::Scheduled,
+            State::Disabled => proto::State::Disabled,
+            State::Completed => proto::State::Completed,
+        }
+    }
+}
+
+impl From<Event> for proto::Event {
+    fn from(event: Event) -> Self {
+        proto::Event {
+            id: event.id().to_string(),
+            namespace: event.namespace().to_string(),
+            key: event.key().to_string(),
+            value: event.value().to_string(),
+            idempotence_key: event.idempotence_key().to_string(),
+            state: proto::State::from(event.state()) as i32,
+            created_at: Some(to_timestamp(event.created_at())),
+            scheduled_at: Some(to_timestamp(event.schedule_at())),
+            version: event.version(),
+            previous_id: event
+                .previous_id()
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            series_id: event.series_id().to_string(),
+        }
+    }
+}
+
+impl From<Change> for proto::WatchResponse {
+    fn from(change: Change) -> Self {
+        let kind: Kind = match change.op() {
+            Op::Insert => Kind::Insert,
+            Op::Update => Kind::Transition,
+        };
+
+        proto::WatchResponse {
+            kind: kind as i32,
+            event_id: change.id().to_string(),
+            key: change.key().to_string(),
+            old_state: change
+                .old_state()
+                .map(proto::State::from)
+                .unwrap_or(proto::State::Unspecified) as i32,
+            state: proto::State::from(change.state()) as i32,
+            scheduled_at: Some(to_timestamp(change.scheduled_at())),
+            event: None,
+        }
+    }
+}
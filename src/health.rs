@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::db::event::EventRepoPgsql;
+
+/// How often the database is checked while it is reachable
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Shortest and longest time to wait before trying again after failing to reach the database
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Names of the checks readiness depends on
+pub const DATABASE: &str = "database";
+pub const FEED: &str = "feed";
+pub const SCHEMA: &str = "schema";
+pub const STATEMENTS: &str = "statements";
+
+/// Outcome of the latest check of something the service depends on
+#[derive(Serialize, Debug, Clone)]
+pub struct Check {
+    up: bool,
+    since: chrono::DateTime<chrono::Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip)]
+    pending: bool,
+}
+
+impl Check {
+    pub fn up(&self) -> bool {
+        self.up
+    }
+}
+
+/// Whether the service is ready to serve requests, which it is when every dependency is up.
+/// Checks are reported by the tasks that keep the dependencies connected, and are pending until
+/// they first report.
+#[derive(Clone)]
+pub struct Readiness {
+    checks: Arc<Mutex<BTreeMap<&'static str, Check>>>,
+}
+
+impl Readiness {
+    pub fn new(names: &[&'static str]) -> Readiness {
+        let pending = Check {
+            up: false,
+            since: chrono::Utc::now(),
+            error: Some(String::from("Not checked yet")),
+            pending: true,
+        };
+        let checks = names.iter().map(|name| (*name, pending.clone())).collect();
+
+        Readiness {
+            checks: Arc::new(Mutex::new(checks)),
+        }
+    }
+
+    /// Record the outcome of checking a dependency, logging when it goes down or comes back up
+    pub fn report(&self, name: &'static str, res: Result<(), String>) {
+        let mut checks = self.checks.lock().unwrap();
+        let was: Option<(bool, bool)> = checks.get(name).map(|c| (c.up, c.pending));
+
+        match (&res, was) {
+            (Ok(()), Some((_, true)) | None) => info!("{} is up", name),
+            (Ok(()), Some((false, false))) => info!("{} is up again", name),
+            (Err(e), Some((true, _) | (_, true)) | None) => warn!("{} is down: {}", name, e),
+            _ => (),
+        }
+
+        let since = match (checks.get(name), res.is_ok()) {
+            (Some(check), up) if check.up == up && !check.pending => check.since,
+            _ => chrono::Utc::now(),
+        };
+
+        checks.insert(
+            name,
+            Check {
+                up: res.is_ok(),
+                since,
+                error: res.err(),
+                pending: false,
+            },
+        );
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.checks.lock().unwrap().values().all(Check::up)
+    }
+
+    pub fn checks(&self) -> BTreeMap<&'static str, Check> {
+        self.checks.lock().unwrap().clone()
+    }
+}
+
+/// Exponentially growing delay between attempts to reconnect
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        Backoff { next: MIN_BACKOFF }
+    }
+
+    /// Time to wait before the next attempt, which is twice as long as the previous one, up to
+    /// a limit
+    pub fn next(&mut self) -> Duration {
+        let wait: Duration = self.next;
+        self.next = (self.next * 2).min(MAX_BACKOFF);
+        wait
+    }
+
+    pub fn reset(&mut self) {
+        self.next = MIN_BACKOFF;
+    }
+}
+
+/// Check that the database can be reached through the connection pool, periodically while it
+/// can and with backoff while it cannot. Broken connections are replaced by the pool as they are
+/// found, so requests succeed again as soon as the database is back. While it can be reached,
+/// the schema and the statements prepared against it are checked too.
+pub async fn watch_database(repo: EventRepoPgsql, readiness: Readiness) {
+    let mut backoff = Backoff::new();
+
+    loop {
+        let wait: Duration = match repo.ping().await {
+            Ok(()) => {
+                readiness.report(DATABASE, Ok(()));
+                readiness.report(SCHEMA, repo.check_schema().await);
+                readiness.report(STATEMENTS, repo.check_statements().await);
+                backoff.reset();
+                CHECK_INTERVAL
+            }
+            Err(e) => {
+                readiness.report(DATABASE, Err(e));
+                backoff.next()
+            }
+        };
+
+        tokio::time::sleep(wait).await;
+    }
+}
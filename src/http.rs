@@ -1,12 +1,19 @@
 pub mod event {
-    use log::error;
-    use serde::Deserialize;
+    use std::io;
+
+    use futures::channel::mpsc;
+    use futures::{pin_mut, AsyncReadExt, SinkExt, StreamExt, TryStreamExt};
+    use log::{error, info};
+    use serde::{Deserialize, Serialize};
     use serde_json::json;
     use tide::Request;
 
     use crate::{
-        db::event::EventRepoPgsql,
-        event::{Event, State},
+        csv,
+        db::event::{BatchOp, BatchResult, EventRepoPgsql, RepoErr},
+        event::{Event, Overdue, State, Transition},
+        ics,
+        poll::Poller,
         search::SearchQuery,
     };
 
@@ -14,14 +21,21 @@ pub mod event {
         let event: CreateEvent = req.body_json().await?;
 
         let repo: &EventRepoPgsql = req.state();
-        match repo.insert(event.clone()).await {
-            Ok(event) => Ok(tide::Response::builder(200)
-                .body(serde_json::to_string(&event).unwrap())
-                .build()),
+        match repo.insert(event.clone(), caller(&req)?).await {
+            Ok(event) => ok_event(&event),
             Err(e) => {
                 let (code, msg): (u16, &str) = match e {
                     crate::db::event::RepoErr::AlreadyScheduled => (409, "Unable to insert"),
-                    _ => (500, "Internal Server Error"),
+                    crate::db::event::RepoErr::IdempotenceConflict => {
+                        (422, "Idempotence key is used by another event")
+                    }
+                    e => {
+                        error!(
+                            namespace = event.namespace(), key = event.key();
+                            "Error scheduling event, {:?}", e
+                        );
+                        (500, "Internal Server Error")
+                    }
                 };
                 let err = tide::Error::from_str(code, msg);
                 tide::Result::Err(err)
@@ -31,12 +45,17 @@ pub mod event {
 
     pub async fn search_events(mut req: Request<EventRepoPgsql>) -> tide::Result {
         let query: SearchQuery = req.body_json().await?;
+        if accepts(&req, "text/csv") {
+            return search_csv(&req, &query).await;
+        }
+
         let repo: &EventRepoPgsql = req.state();
         let events: Vec<Event> = match repo.search(&query).await {
             Ok(events) => events,
             Err(e) => {
-                error!("Error searching, {:?}", e);
-                return Err(e.into());
+                error!(namespace = query.namespace(); "Error searching, {:?}", e);
+                let (code, msg) = repo_err_status(&e);
+                return err(code, msg);
             }
         };
         let (min, max) = query.scheduled_at();
@@ -52,48 +71,581 @@ pub mod event {
         ok(200, body)
     }
 
+    /// Write the search result as CSV, one row at a time as events are read from the database, so
+    /// that large results can be downloaded without holding them all in memory. Like export, there
+    /// is no default limit on the number of events.
+    async fn search_csv(req: &Request<EventRepoPgsql>, query: &SearchQuery) -> tide::Result {
+        let options: CsvQuery = req.query()?;
+        let columns = csv::Columns::new(&options.paths());
+
+        let repo: &EventRepoPgsql = req.state();
+        let events = match repo.stream(query).await {
+            Ok(events) => events,
+            Err(e) => {
+                error!(namespace = query.namespace(); "Error searching, {:?}", e);
+                return err(500, "Internal Server Error");
+            }
+        };
+
+        let (mut sender, receiver) = mpsc::channel::<io::Result<String>>(64);
+        async_std::task::spawn(async move {
+            pin_mut!(events);
+            if sender.send(Ok(columns.header())).await.is_err() {
+                return;
+            }
+
+            while let Some(event) = events.next().await {
+                let row: io::Result<String> = match event {
+                    Ok(event) => Ok(columns.row(&event)),
+                    Err(e) => {
+                        error!("Error streaming search result, {:?}", e);
+                        Err(io::Error::other("Error reading events"))
+                    }
+                };
+
+                let failed: bool = row.is_err();
+                if sender.send(row).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        let res = tide::Response::builder(200)
+            .content_type("text/csv; charset=utf-8")
+            .body(tide::Body::from_reader(receiver.into_async_read(), None))
+            .build();
+
+        Ok(res)
+    }
+
+    /// Wait until at least one scheduled event in the namespace is due, and return the due events,
+    /// or respond with no content if none became due before the timeout.
+    pub async fn poll_events(mut req: Request<EventRepoPgsql>) -> tide::Result {
+        let poll: PollRequest = req.body_json().await?;
+        let poller: &Poller = match req.ext::<Poller>() {
+            Some(poller) => poller,
+            None => return err(503, "Polling is not available"),
+        };
+
+        let events: Vec<Event> = poller
+            .wait(&poll.namespace, poll.timeout(), poll.limit.unwrap_or(1))
+            .await;
+
+        if events.is_empty() {
+            return ok(204, "");
+        }
+
+        let body = json!({
+            "namespace": poll.namespace,
+            "events": events
+        });
+
+        ok(200, body)
+    }
+
+    pub async fn overdue_events(req: Request<EventRepoPgsql>) -> tide::Result {
+        let repo: &EventRepoPgsql = req.state();
+        let overdue: Vec<Overdue> = match repo.overdue().await {
+            Ok(overdue) => overdue,
+            Err(e) => {
+                error!("Error finding overdue events, {:?}", e);
+                return err(500, "Internal Server Error");
+            }
+        };
+
+        ok(200, json!({ "namespaces": overdue }))
+    }
+
     pub async fn settle_event(mut req: Request<EventRepoPgsql>) -> tide::Result {
+        let version: Option<i64> = if_match(&req)?;
         let update: SettleEvent = req.body_json().await?;
         let repo: &EventRepoPgsql = req.state();
-        let res = repo.change_state(&update).await;
+        let res = repo.change_state(&update, version, caller(&req)?).await;
 
         match res {
-            Ok(Some(event)) => ok(200, serde_json::to_string(&event).unwrap()),
+            Ok(Some(event)) => ok_event(&event),
             Ok(None) => match repo.get(&update.key, update.id, &update.namespace).await {
-                Ok(Some(event)) => ok(200, serde_json::to_string(&event).unwrap()),
-                Ok(None) => err(404, "Event not found"),
-                Err(e) => match e {
-                    crate::db::event::RepoErr::Connection => todo!(),
-                    crate::db::event::RepoErr::AlreadyScheduled => todo!(),
-                    crate::db::event::RepoErr::IllegalState => todo!(),
-                    crate::db::event::RepoErr::Conversion => todo!(),
-                    crate::db::event::RepoErr::NoResult => todo!(),
-                    crate::db::event::RepoErr::Other(_) => todo!(),
-                    crate::db::event::RepoErr::Unknown => todo!(),
+                Ok(Some(event)) => match version {
+                    Some(version) if version != event.version() => {
+                        err(412, "Event has been modified")
+                    }
+                    _ => ok_event(&event),
                 },
+                Ok(None) => err(404, "Event not found"),
+                Err(e) => {
+                    if let RepoErr::Other(_) | RepoErr::Unknown = e {
+                        error!(
+                            namespace = update.namespace.as_str(), key = update.key.as_str(), event_id:% = update.id;
+                            "Error fetching settled event, {:?}", e
+                        );
+                    }
+                    let (code, msg) = repo_err_status(&e);
+                    err(code, msg)
+                }
             },
-            Err(e) => match e {
-                crate::db::event::RepoErr::Connection => todo!(),
-                crate::db::event::RepoErr::AlreadyScheduled => todo!(),
-                crate::db::event::RepoErr::IllegalState => err(409, ""),
-                crate::db::event::RepoErr::Conversion => todo!(),
-                crate::db::event::RepoErr::NoResult => todo!(),
-                crate::db::event::RepoErr::Other(_) => todo!(),
-                crate::db::event::RepoErr::Unknown => err(500, ""),
-            },
+            Err(e) => {
+                if let RepoErr::Other(_) | RepoErr::Unknown = e {
+                    error!(
+                        namespace = update.namespace.as_str(), key = update.key.as_str(), event_id:% = update.id;
+                        "Error settling event, {:?}", e
+                    );
+                }
+                let (code, msg) = repo_err_status(&e);
+                err(code, msg)
+            }
         }
     }
 
     pub async fn settle_and_next(mut req: Request<EventRepoPgsql>) -> tide::Result {
+        let version: Option<i64> = if_match(&req)?;
         let settle: SettleAndNextEvent = req.body_json().await?;
         let repo: &EventRepoPgsql = req.state();
-        match repo.update_and_insert(&settle).await {
-            Ok(event) => ok(200, serde_json::to_string(&event).unwrap()),
-            Err(_) => err(400, "Unable to perform settle and schedule"),
+        match repo
+            .update_and_insert(&settle, version, caller(&req)?)
+            .await
+        {
+            Ok(event) => ok_event(&event),
+            Err(RepoErr::VersionConflict) => err(412, "Event has been modified"),
+            Err(RepoErr::IdempotenceConflict) => {
+                err(422, "Idempotence key is used by another event")
+            }
+            Err(e) => {
+                info!(
+                    namespace = settle.namespace.as_str(), key = settle.key.as_str(), event_id:% = settle.id;
+                    "Unable to settle event and schedule next event, {:?}", e
+                );
+                err(400, "Unable to perform settle and schedule")
+            }
+        }
+    }
+
+    pub async fn schedule_batch(mut req: Request<EventRepoPgsql>) -> tide::Result {
+        let batch: Batch<CreateEvent> = req.body_json().await?;
+        let ops: Vec<BatchOp> = batch.events.iter().map(BatchOp::Create).collect();
+        apply_batch(&req, &ops, batch.mode()).await
+    }
+
+    pub async fn settle_batch(mut req: Request<EventRepoPgsql>) -> tide::Result {
+        let batch: Batch<SettleEvent> = req.body_json().await?;
+        let ops: Vec<BatchOp> = batch.events.iter().map(BatchOp::Settle).collect();
+        apply_batch(&req, &ops, batch.mode()).await
+    }
+
+    pub async fn settle_and_next_batch(mut req: Request<EventRepoPgsql>) -> tide::Result {
+        let batch: Batch<SettleAndNextEvent> = req.body_json().await?;
+        let ops: Vec<BatchOp> = batch.events.iter().map(BatchOp::SettleAndNext).collect();
+        apply_batch(&req, &ops, batch.mode()).await
+    }
+
+    async fn apply_batch(
+        req: &Request<EventRepoPgsql>,
+        ops: &[BatchOp<'_>],
+        mode: BatchMode,
+    ) -> tide::Result {
+        let repo: &EventRepoPgsql = req.state();
+        let results: Vec<BatchResult> = match repo.batch(ops, mode, caller(req)?).await {
+            Ok(results) => results,
+            Err(e) => {
+                error!(events = ops.len(); "Error applying batch, {:?}", e);
+                return err(500, "Internal Server Error");
+            }
+        };
+
+        let failure: Option<u16> = match mode {
+            BatchMode::Atomic => results.iter().find_map(|res| res.as_ref().err()).map(|e| {
+                let (code, _) = repo_err_status(e);
+                code
+            }),
+            BatchMode::Partial => None,
+        };
+
+        let items: Vec<serde_json::Value> = (0..ops.len())
+            .map(|index| match (results.get(index), failure) {
+                (Some(Ok(_)), Some(_)) | (None, _) => json!({
+                    "index": index,
+                    "status": 424,
+                    "error": "Batch was rolled back"
+                }),
+                (Some(Ok(event)), None) => json!({
+                    "index": index,
+                    "status": 200,
+                    "event": event
+                }),
+                (Some(Err(e)), _) => {
+                    let (code, msg) = repo_err_status(e);
+                    json!({
+                        "index": index,
+                        "status": code,
+                        "error": msg
+                    })
+                }
+            })
+            .collect();
+
+        let body = json!({
+            "mode": mode,
+            "committed": failure.is_none(),
+            "results": items
+        });
+
+        ok(failure.unwrap_or(200), body)
+    }
+
+    fn repo_err_status(e: &RepoErr) -> (u16, &'static str) {
+        match e {
+            RepoErr::AlreadyScheduled => (409, "Event is already scheduled"),
+            RepoErr::IdempotenceConflict => (422, "Idempotence key is used by another event"),
+            RepoErr::IllegalState => (409, "Illegal state"),
+            RepoErr::NoResult => (404, "Event not found"),
+            RepoErr::VersionConflict => (412, "Event has been modified"),
+            RepoErr::Connection => (503, "Database is unavailable"),
+            _ => (500, "Internal Server Error"),
+        }
+    }
+
+    pub async fn event_history(req: Request<EventRepoPgsql>) -> tide::Result {
+        let namespace: &str = req.param("namespace")?;
+        let id: uuid::Uuid = match req.param("id")?.parse() {
+            Ok(id) => id,
+            Err(_) => return err(400, "Invalid event id"),
+        };
+
+        let repo: &EventRepoPgsql = req.state();
+        match repo.history(namespace, id).await {
+            Ok(Some(transitions)) => ok(
+                200,
+                json!({
+                    "namespace": namespace,
+                    "id": id,
+                    "transitions": transitions
+                }),
+            ),
+            Ok(None) => err(404, "Event not found"),
+            Err(e) => {
+                error!(namespace = namespace, event_id:% = id; "Error fetching event history, {:?}", e);
+                err(500, "Internal Server Error")
+            }
+        }
+    }
+
+    pub async fn series_events(req: Request<EventRepoPgsql>) -> tide::Result {
+        let namespace: &str = req.param("namespace")?;
+        let series_id: uuid::Uuid = match req.param("id")?.parse() {
+            Ok(id) => id,
+            Err(_) => return err(400, "Invalid series id"),
+        };
+
+        let repo: &EventRepoPgsql = req.state();
+        match repo.series(namespace, series_id).await {
+            Ok(events) if events.is_empty() => err(404, "Series not found"),
+            Ok(events) => ok(
+                200,
+                json!({
+                    "namespace": namespace,
+                    "seriesId": series_id,
+                    "events": events
+                }),
+            ),
+            Err(e) => {
+                error!(namespace = namespace, series_id:% = series_id; "Error fetching series, {:?}", e);
+                err(500, "Internal Server Error")
+            }
         }
     }
 
-    fn ok<S, M>(status: S, msg: M) -> tide::Result
+    pub async fn event_lineage(req: Request<EventRepoPgsql>) -> tide::Result {
+        let namespace: &str = req.param("namespace")?;
+        let id: uuid::Uuid = match req.param("id")?.parse() {
+            Ok(id) => id,
+            Err(_) => return err(400, "Invalid event id"),
+        };
+
+        let repo: &EventRepoPgsql = req.state();
+        match repo.lineage(namespace, id).await {
+            Ok(events) if events.is_empty() => err(404, "Event not found"),
+            Ok(events) => ok(
+                200,
+                json!({
+                    "namespace": namespace,
+                    "id": id,
+                    "events": events
+                }),
+            ),
+            Err(e) => {
+                error!(namespace = namespace, event_id:% = id; "Error fetching event lineage, {:?}", e);
+                err(500, "Internal Server Error")
+            }
+        }
+    }
+
+    pub async fn calendar(req: Request<EventRepoPgsql>) -> tide::Result {
+        let filter: CalendarQuery = req.query()?;
+        let namespace: String = req.param("namespace")?.to_string();
+        let state: Option<Vec<State>> = match filter.state {
+            Some(state) => match state.split(',').map(str::parse).collect() {
+                Ok(state) => Some(state),
+                Err(e) => return err(400, e),
+            },
+            None => Some(vec![State::Scheduled]),
+        };
+
+        let query = SearchQuery::new(
+            namespace,
+            filter.key,
+            state,
+            filter.limit,
+            filter.scheduled_at_min,
+            filter.scheduled_at_max,
+        );
+
+        let repo: &EventRepoPgsql = req.state();
+        let events = match repo.stream(&query).await {
+            Ok(events) => events,
+            Err(e) => {
+                error!(namespace = query.namespace(); "Error searching, {:?}", e);
+                let (code, msg) = repo_err_status(&e);
+                return err(code, msg);
+            }
+        };
+
+        let namespace: String = query.namespace().to_string();
+        let (mut sender, receiver) = mpsc::channel::<io::Result<String>>(64);
+        async_std::task::spawn(async move {
+            pin_mut!(events);
+            if sender
+                .send(Ok(ics::calendar_start(&namespace)))
+                .await
+                .is_err()
+            {
+                return;
+            }
+
+            let now: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
+            while let Some(event) = events.next().await {
+                let vevent: io::Result<String> = match event {
+                    Ok(event) => Ok(ics::vevent(&event, &now)),
+                    Err(e) => {
+                        error!(namespace = namespace.as_str(); "Error streaming calendar, {:?}", e);
+                        Err(io::Error::other("Error reading events"))
+                    }
+                };
+
+                let failed: bool = vevent.is_err();
+                if sender.send(vevent).await.is_err() || failed {
+                    return;
+                }
+            }
+
+            let _ = sender.send(Ok(ics::calendar_end())).await;
+        });
+
+        let res = tide::Response::builder(200)
+            .content_type("text/calendar; charset=utf-8")
+            .body(tide::Body::from_reader(receiver.into_async_read(), None))
+            .build();
+
+        Ok(res)
+    }
+
+    pub async fn import_calendar(mut req: Request<EventRepoPgsql>) -> tide::Result {
+        let options: ImportQuery = req.query()?;
+        let horizon: std::time::Duration = match options.horizon.as_deref() {
+            Some(horizon) => match humantime::parse_duration(horizon) {
+                Ok(horizon) => horizon,
+                Err(e) => return err(400, e),
+            },
+            None => ics::DEFAULT_HORIZON,
+        };
+
+        let mut input = String::new();
+        req.take_body()
+            .take(ics::MAX_INPUT_LEN + 1)
+            .read_to_string(&mut input)
+            .await?;
+        if input.len() as u64 > ics::MAX_INPUT_LEN {
+            return err(413, "Calendar is larger than 4 MiB");
+        }
+
+        let namespace: String = req.param("namespace")?.to_string();
+        let dry_run: bool = options.dry_run.unwrap_or(false);
+
+        let repo: &EventRepoPgsql = req.state();
+        let items: Vec<ics::ImportItem> =
+            match ics::import(repo, &namespace, &input, horizon, dry_run, caller(&req)?).await {
+                Ok(items) => items,
+                Err(ics::ImportErr::Rejected(reason)) => return err(400, reason),
+                Err(ics::ImportErr::Repo(e)) => {
+                    error!(namespace = namespace.as_str(); "Error importing calendar, {:?}", e);
+                    return err(500, "Internal Server Error");
+                }
+            };
+
+        let (mut scheduled, mut conflicts, mut failed) = (0, 0, 0);
+        let results: Vec<serde_json::Value> = items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| match item {
+                ics::ImportItem::Parsed(occurrence, res) => {
+                    let mut result = json!({
+                        "index": index,
+                        "uid": occurrence.uid(),
+                        "key": occurrence.key(),
+                        "scheduledAt": occurrence.scheduled_at(),
+                    });
+                    match res {
+                        Ok(event) => {
+                            scheduled += 1;
+                            result["status"] = json!(200);
+                            result["event"] = json!(event);
+                        }
+                        Err(e) => {
+                            match e {
+                                RepoErr::AlreadyScheduled => conflicts += 1,
+                                _ => failed += 1,
+                            }
+                            let (code, msg) = repo_err_status(e);
+                            result["status"] = json!(code);
+                            result["error"] = json!(msg);
+                        }
+                    }
+                    result
+                }
+                ics::ImportItem::Invalid(invalid) => {
+                    failed += 1;
+                    json!({
+                        "index": index,
+                        "uid": invalid.uid(),
+                        "status": 422,
+                        "error": invalid.error()
+                    })
+                }
+            })
+            .collect();
+
+        let body = json!({
+            "namespace": namespace,
+            "dryRun": dry_run,
+            "scheduled": scheduled,
+            "conflicts": conflicts,
+            "failed": failed,
+            "results": results
+        });
+
+        ok(200, body)
+    }
+
+    /// Whether the client asks for responses of the given media type by name, and prefers them at
+    /// least as much as JSON, which is what is answered otherwise
+    fn accepts(req: &Request<EventRepoPgsql>, media_type: &str) -> bool {
+        let accept: String = match req.header("Accept") {
+            Some(values) => values
+                .iter()
+                .map(|value| value.as_str())
+                .collect::<Vec<&str>>()
+                .join(","),
+            None => return false,
+        };
+
+        prefers(&accept, media_type)
+    }
+
+    /// Whether an Accept header names the media type with a quality above zero, and no lower than
+    /// the quality of JSON. Wildcards are only taken into account for JSON.
+    fn prefers(accept: &str, media_type: &str) -> bool {
+        let ranges: Vec<(String, f32)> = media_ranges(accept);
+        let wanted: f32 = quality(&ranges, &[media_type]);
+        let json: f32 = quality(&ranges, &["application/json", "application/*", "*/*"]);
+
+        wanted > 0.0 && wanted >= json
+    }
+
+    /// Media ranges of an Accept header with their quality. Ranges with an invalid quality are
+    /// left out.
+    fn media_ranges(accept: &str) -> Vec<(String, f32)> {
+        accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let media_type: String = parts.next()?.trim().to_ascii_lowercase();
+                if media_type.is_empty() {
+                    return None;
+                }
+
+                let mut quality: f32 = 1.0;
+                for param in parts {
+                    if let Some((name, value)) = param.split_once('=') {
+                        if name.trim().eq_ignore_ascii_case("q") {
+                            quality = value
+                                .trim()
+                                .parse()
+                                .ok()
+                                .filter(|q| (0.0..=1.0).contains(q))?;
+                        }
+                    }
+                }
+
+                Some((media_type, quality))
+            })
+            .collect()
+    }
+
+    /// Quality of the first of the given media ranges that is in the Accept header, from the most
+    /// to the least specific, or zero if none of them are
+    fn quality(ranges: &[(String, f32)], media_ranges: &[&str]) -> f32 {
+        media_ranges
+            .iter()
+            .find_map(|media_range| {
+                ranges
+                    .iter()
+                    .find(|(range, _)| range == media_range)
+                    .map(|(_, quality)| *quality)
+            })
+            .unwrap_or(0.0)
+    }
+
+    /// Identity of the caller making a change, which is recorded in the history of the event.
+    fn caller(req: &Request<EventRepoPgsql>) -> Result<Option<&str>, tide::Error> {
+        match req
+            .header("X-Caller-Id")
+            .map(|values| values.last().as_str())
+        {
+            Some(caller) if !Transition::valid_changed_by(caller) => {
+                Err(tide::Error::from_str(400, "Invalid X-Caller-Id header"))
+            }
+            caller => Ok(caller),
+        }
+    }
+
+    /// Read the expected event version from an `If-Match` header, where the entity tag is the
+    /// version number of the event, as returned in the `ETag` header.
+    fn if_match(req: &Request<EventRepoPgsql>) -> Result<Option<i64>, tide::Error> {
+        let value: &str = match req.header("If-Match") {
+            Some(values) => values.last().as_str().trim(),
+            None => return Ok(None),
+        };
+
+        if value == "*" {
+            return Ok(None);
+        }
+
+        value
+            .trim_start_matches("W/")
+            .trim_matches('"')
+            .parse::<i64>()
+            .map(Some)
+            .map_err(|_| tide::Error::from_str(400, "Invalid If-Match header"))
+    }
+
+    fn ok_event(event: &Event) -> tide::Result {
+        let res = tide::Response::builder(200)
+            .header("ETag", format!("\"{}\"", event.version()))
+            .body(serde_json::to_string(event).unwrap())
+            .build();
+
+        Ok(res)
+    }
+
+    pub(super) fn ok<S, M>(status: S, msg: M) -> tide::Result
     where
         S: TryInto<tide::StatusCode>,
         S::Error: std::fmt::Debug,
@@ -106,7 +658,7 @@ pub mod event {
         Ok(res)
     }
 
-    fn err<S, M>(status: S, msg: M) -> tide::Result
+    pub(super) fn err<S, M>(status: S, msg: M) -> tide::Result
     where
         S: TryInto<tide::StatusCode>,
         S::Error: std::fmt::Debug,
@@ -115,6 +667,87 @@ pub mod event {
         tide::Result::Err(tide::Error::from_str(status, msg))
     }
 
+    /// Longest time a client may wait for a due event, and the default if no timeout is given
+    const MAX_POLL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+    const DEFAULT_POLL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct PollRequest {
+        namespace: String,
+        #[serde(alias = "timeoutMs")]
+        timeout_ms: Option<u64>,
+        limit: Option<usize>,
+    }
+
+    impl PollRequest {
+        pub fn timeout(&self) -> std::time::Duration {
+            match self.timeout_ms {
+                Some(ms) => std::time::Duration::from_millis(ms).min(MAX_POLL_TIMEOUT),
+                None => DEFAULT_POLL_TIMEOUT,
+            }
+        }
+    }
+
+    /// Paths into the `value` of events to include as columns in CSV, separated by comma
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct CsvQuery {
+        paths: Option<String>,
+    }
+
+    impl CsvQuery {
+        pub fn paths(&self) -> Vec<String> {
+            match &self.paths {
+                Some(paths) => paths.split(',').map(String::from).collect(),
+                None => Vec::new(),
+            }
+        }
+    }
+
+    /// Filters for the calendar feed, given as query parameters. States are separated by comma,
+    /// and only scheduled events are included unless other states are asked for. All matching
+    /// events are included unless a limit is given.
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct CalendarQuery {
+        key: Option<String>,
+        state: Option<String>,
+        limit: Option<u32>,
+        #[serde(alias = "scheduledAtMin")]
+        scheduled_at_min: Option<chrono::DateTime<chrono::Utc>>,
+        #[serde(alias = "scheduledAtMax")]
+        scheduled_at_max: Option<chrono::DateTime<chrono::Utc>>,
+    }
+
+    /// Options for importing an iCalendar file, given as query parameters. The horizon is a
+    /// duration such as "90d" or "12h".
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct ImportQuery {
+        horizon: Option<String>,
+        #[serde(alias = "dryRun")]
+        dry_run: Option<bool>,
+    }
+
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct Batch<T> {
+        mode: Option<BatchMode>,
+        events: Vec<T>,
+    }
+
+    impl<T> Batch<T> {
+        pub fn mode(&self) -> BatchMode {
+            self.mode.unwrap_or(BatchMode::Atomic)
+        }
+    }
+
+    /// Whether a batch should be applied all-or-nothing, or if successful operations should be
+    /// kept even when others in the same batch fail.
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+    pub enum BatchMode {
+        #[serde(alias = "ATOMIC")]
+        Atomic,
+        #[serde(alias = "PARTIAL")]
+        Partial,
+    }
+
     #[derive(Deserialize, Debug, Clone)]
     pub struct SettleEvent {
         pub key: String,
@@ -130,9 +763,38 @@ pub mod event {
         namespace: String,
         #[serde(alias = "scheduleAt")]
         schedule_at: String,
+        #[serde(alias = "idempotenceKey")]
+        idempotence_key: Option<uuid::Uuid>,
+        conflict: Option<Conflict>,
     }
 
     impl CreateEvent {
+        pub fn new(
+            key: String,
+            value: serde_json::Value,
+            namespace: String,
+            schedule_at: chrono::DateTime<chrono::Utc>,
+        ) -> CreateEvent {
+            CreateEvent {
+                key,
+                value: Some(value),
+                namespace,
+                schedule_at: schedule_at.to_rfc3339(),
+                idempotence_key: None,
+                conflict: None,
+            }
+        }
+
+        pub fn with_idempotence_key(mut self, idempotence_key: Option<uuid::Uuid>) -> CreateEvent {
+            self.idempotence_key = idempotence_key;
+            self
+        }
+
+        pub fn with_conflict(mut self, conflict: Conflict) -> CreateEvent {
+            self.conflict = Some(conflict);
+            self
+        }
+
         pub fn key(&self) -> &str {
             &self.key
         }
@@ -145,6 +807,14 @@ pub mod event {
             &self.namespace
         }
 
+        pub fn idempotence_key(&self) -> Option<uuid::Uuid> {
+            self.idempotence_key
+        }
+
+        pub fn conflict(&self) -> Conflict {
+            self.conflict.unwrap_or(Conflict::Reject)
+        }
+
         pub fn schedule_at(&self) -> Result<chrono::DateTime<chrono::Utc>, String> {
             let timestamp =
                 chrono::DateTime::parse_from_rfc3339(self.schedule_at.as_str()).unwrap();
@@ -152,18 +822,51 @@ pub mod event {
         }
     }
 
+    /// How to handle an event being created when there already is a scheduled event for the same
+    /// namespace and key.
+    #[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum Conflict {
+        /// Fail the insert, leaving the existing event as it is
+        Reject,
+        /// Disable the existing event and insert the new one
+        Replace,
+        /// Keep whichever of the existing and the new event that is scheduled first
+        KeepEarliest,
+        /// Keep whichever of the existing and the new event that is scheduled last
+        KeepLatest,
+    }
+
     #[derive(Deserialize, Debug, Clone)]
     pub struct NextEvent {
         #[serde(alias = "scheduleAt")]
         schedule_at: String,
         value: Option<serde_json::Value>,
+        #[serde(alias = "idempotenceKey")]
+        idempotence_key: Option<uuid::Uuid>,
     }
 
     impl NextEvent {
+        pub fn new(
+            schedule_at: chrono::DateTime<chrono::Utc>,
+            value: serde_json::Value,
+            idempotence_key: Option<uuid::Uuid>,
+        ) -> NextEvent {
+            NextEvent {
+                schedule_at: schedule_at.to_rfc3339(),
+                value: Some(value),
+                idempotence_key,
+            }
+        }
+
         pub fn value(&self) -> serde_json::Value {
             self.value.clone().unwrap_or(serde_json::Value::Null)
         }
 
+        pub fn idempotence_key(&self) -> Option<uuid::Uuid> {
+            self.idempotence_key
+        }
+
         pub fn schedule_at(&self) -> Result<chrono::DateTime<chrono::Utc>, String> {
             let timestamp =
                 chrono::DateTime::parse_from_rfc3339(self.schedule_at.as_str()).unwrap();
@@ -179,6 +882,461 @@ pub mod event {
         pub state: State,
         pub next: NextEvent,
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn csv_by_name() {
+            let accept = [
+                "text/csv",
+                "Text/CSV",
+                "text/csv; charset=utf-8",
+                "application/json, text/csv",
+                "text/csv;q=0.9, */*;q=0.1",
+                "application/json;q=0.5, text/csv;q=0.6",
+            ];
+            for accept in accept {
+                assert!(prefers(accept, "text/csv"), "{}", accept);
+            }
+        }
+
+        #[test]
+        fn json_otherwise() {
+            let accept = [
+                "",
+                "*/*",
+                "text/*",
+                "application/json",
+                "text/csvx",
+                "text/csv-schema",
+                "application/text/csv",
+                "text/csv;q=0",
+                "text/csv;q=0.0, application/json;q=0.1",
+                "text/csv;q=invalid",
+                "text/csv;q=2",
+                "application/json, text/csv;q=0.5",
+                "*/*, text/csv;q=0.5",
+            ];
+            for accept in accept {
+                assert!(!prefers(accept, "text/csv"), "{}", accept);
+            }
+        }
+
+        #[test]
+        fn most_specific_json_range() {
+            assert!(!prefers(
+                "application/json, */*;q=0.1, text/csv;q=0.5",
+                "text/csv"
+            ));
+            assert!(prefers(
+                "application/json;q=0.1, */*, text/csv;q=0.5",
+                "text/csv"
+            ));
+        }
+    }
+}
+
+pub mod retention {
+    use log::error;
+    use serde::Deserialize;
+    use serde_json::json;
+    use tide::Request;
+
+    use super::event::{err, ok};
+    use crate::{db::event::EventRepoPgsql, retention::RetentionPolicy};
+
+    pub async fn list_retention_policies(req: Request<EventRepoPgsql>) -> tide::Result {
+        let repo: &EventRepoPgsql = req.state();
+        match repo.retention_policies().await {
+            Ok(policies) => ok(200, json!({ "policies": policies })),
+            Err(e) => {
+                error!("Error listing retention policies, {:?}", e);
+                err(500, "Internal Server Error")
+            }
+        }
+    }
+
+    pub async fn get_retention_policy(req: Request<EventRepoPgsql>) -> tide::Result {
+        let namespace: &str = req.param("namespace")?;
+        let repo: &EventRepoPgsql = req.state();
+        match repo.retention_policy(namespace).await {
+            Ok(Some(policy)) => ok(200, json!(policy)),
+            Ok(None) => err(404, "No retention policy for namespace"),
+            Err(e) => {
+                error!(namespace = namespace; "Error fetching retention policy, {:?}", e);
+                err(500, "Internal Server Error")
+            }
+        }
+    }
+
+    pub async fn set_retention_policy(mut req: Request<EventRepoPgsql>) -> tide::Result {
+        let update: SetRetentionPolicy = req.body_json().await?;
+        let namespace: String = req.param("namespace")?.to_string();
+        let policy = RetentionPolicy::new(namespace, update.max_age_seconds, update.max_count);
+
+        let repo: &EventRepoPgsql = req.state();
+        match repo.set_retention_policy(&policy).await {
+            Ok(policy) => ok(200, json!(policy)),
+            Err(e) => {
+                error!(namespace = policy.namespace(); "Error setting retention policy, {:?}", e);
+                err(400, "Unable to set retention policy")
+            }
+        }
+    }
+
+    pub async fn delete_retention_policy(req: Request<EventRepoPgsql>) -> tide::Result {
+        let namespace: &str = req.param("namespace")?;
+        let repo: &EventRepoPgsql = req.state();
+        match repo.delete_retention_policy(namespace).await {
+            Ok(true) => Ok(tide::Response::new(204)),
+            Ok(false) => err(404, "No retention policy for namespace"),
+            Err(e) => {
+                error!(namespace = namespace; "Error deleting retention policy, {:?}", e);
+                err(500, "Internal Server Error")
+            }
+        }
+    }
+
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct SetRetentionPolicy {
+        #[serde(alias = "maxAgeSeconds")]
+        max_age_seconds: Option<i64>,
+        #[serde(alias = "maxCount")]
+        max_count: Option<i64>,
+    }
+}
+
+pub mod stream {
+    use std::collections::HashSet;
+    use std::fmt;
+    use std::io;
+    use std::str::FromStr;
+    use std::time::{Duration, Instant};
+
+    use chrono::TimeZone;
+    use futures::channel::mpsc;
+    use futures::{SinkExt, TryStreamExt};
+    use log::{error, warn};
+    use serde::Deserialize;
+    use serde_json::json;
+    use tide::sse::Sender;
+    use tide::Request;
+    use tokio::sync::broadcast::error::RecvError;
+
+    use super::event::err;
+    use crate::db::event::{EventRepoPgsql, RepoErr};
+    use crate::event::Event;
+    use crate::feed::{Feed, Notice};
+    use crate::poll::{Due, Poller};
+
+    /// Maximum number of transitions read from the database at a time
+    const BATCH_SIZE: i64 = 100;
+
+    /// How long the stream may be idle before a heartbeat is sent, so that disconnected clients
+    /// are detected even when no events become due.
+    const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+    /// Stream events in a namespace as Server-Sent Events. A `due` message is sent with each event
+    /// that becomes due while still being scheduled, and a `transition` message with each state
+    /// transition if asked for. The id of each message can be given as `Last-Event-ID` to resume
+    /// the stream after the last message that was received.
+    ///
+    /// Due events are shared with everyone else waiting for them in the namespace by the poller,
+    /// and transitions are only read from the database when the change feed has notified a change
+    /// in the namespace. Events that were already due when the stream was started or resumed are
+    /// not sent, unless they are due later than the last due event that was sent, while events
+    /// that become due afterwards are sent once, however long ago they were scheduled for.
+    ///
+    /// The request is only upgraded to a stream once the query is valid and the change feed and
+    /// poller are available, since an error can no longer be answered with a status afterwards.
+    pub async fn due_events(req: Request<EventRepoPgsql>) -> tide::Result {
+        let _: StreamQuery = req.query()?;
+        if req.ext::<Feed>().is_none() || req.ext::<Poller>().is_none() {
+            return err(503, "Change feed is not available");
+        }
+
+        Ok(tide::sse::upgrade(req, stream_due_events))
+    }
+
+    async fn stream_due_events(req: Request<EventRepoPgsql>, sender: Sender) -> tide::Result<()> {
+        let options: StreamQuery = req.query()?;
+        let transitions: bool = options.transitions.unwrap_or(false);
+        let namespace: String = req.param("namespace")?.to_string();
+        let repo: &EventRepoPgsql = req.state();
+
+        let (mut notices, mut due) = match (req.ext::<Feed>(), req.ext::<Poller>()) {
+            (Some(feed), Some(poller)) => (feed.subscribe(), poller.subscribe(&namespace)),
+            _ => return Err(tide::Error::from_str(503, "Change feed is not available")),
+        };
+
+        let mut position: Position = match last_event_id(&req) {
+            Some(position) => position,
+            None => Position {
+                due_at: chrono::Utc::now(),
+                due_id: uuid::Uuid::nil(),
+                transition: repo
+                    .last_transition()
+                    .await
+                    .map_err(|e| stream_err(&namespace, e))?,
+            },
+        };
+
+        let mut notified: Option<HashSet<uuid::Uuid>> = None;
+        let mut due_changed: bool = true;
+        let mut transitions_changed: bool = transitions;
+        let mut last_sent: Instant = Instant::now();
+        loop {
+            let events: Due = match due_changed {
+                true => due.borrow().clone(),
+                false => None,
+            };
+
+            for event in events.iter().flat_map(|events| events.iter()) {
+                let cursor = (*event.schedule_at(), event.id());
+                let sent: bool = match &notified {
+                    Some(notified) => notified.contains(&event.id()),
+                    None => cursor <= (position.due_at, position.due_id),
+                };
+                if sent {
+                    continue;
+                }
+
+                if cursor > (position.due_at, position.due_id) {
+                    (position.due_at, position.due_id) = cursor;
+                }
+                let data: String = serde_json::to_string(event)?;
+                sender
+                    .send("due", data, Some(&position.to_string()))
+                    .await?;
+                last_sent = Instant::now();
+            }
+
+            if let Some(events) = events {
+                notified = Some(events.iter().map(Event::id).collect());
+            }
+
+            while transitions_changed {
+                let changes = repo
+                    .transitions(&namespace, position.transition, BATCH_SIZE)
+                    .await
+                    .map_err(|e| stream_err(&namespace, e))?;
+
+                transitions_changed = changes.len() as i64 == BATCH_SIZE;
+                for (id, transition) in changes {
+                    position.transition = id;
+                    let data: String = serde_json::to_string(&transition)?;
+                    sender
+                        .send("transition", data, Some(&position.to_string()))
+                        .await?;
+                    last_sent = Instant::now();
+                }
+            }
+
+            due_changed = false;
+            let heartbeat: Duration = HEARTBEAT_INTERVAL.saturating_sub(last_sent.elapsed());
+            tokio::select! {
+                notice = notices.recv() => match notice {
+                    Ok(Notice::Change(change)) => {
+                        transitions_changed = transitions && change.namespace() == namespace
+                    }
+                    Ok(Notice::NextDue { .. }) => (),
+                    Err(RecvError::Lagged(_)) => transitions_changed = transitions,
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                changed = due.changed() => match changed {
+                    Ok(()) => due_changed = true,
+                    Err(_) => return Ok(()),
+                },
+                _ = async_std::task::sleep(heartbeat) => {
+                    sender.send("heartbeat", "", None).await?;
+                    last_sent = Instant::now();
+                }
+            }
+        }
+    }
+
+    /// Stream changes to events as they are notified by the database, as JSON objects separated by
+    /// newlines in a chunked response. Changes can be limited to a single namespace. Notices that
+    /// were missed because the client did not keep up are reported as `lagged`, with the number
+    /// of missed notices.
+    pub async fn changes(req: Request<EventRepoPgsql>) -> tide::Result {
+        let options: ChangesQuery = req.query()?;
+        let feed: &Feed = match req.ext::<Feed>() {
+            Some(feed) => feed,
+            None => return err(503, "Change feed is not available"),
+        };
+
+        let mut notices = feed.subscribe();
+        let (mut sender, receiver) = mpsc::channel::<io::Result<String>>(64);
+        async_std::task::spawn(async move {
+            loop {
+                let line: serde_json::Value =
+                    match async_std::future::timeout(HEARTBEAT_INTERVAL, notices.recv()).await {
+                        Ok(Ok(notice)) => match &options.namespace {
+                            Some(namespace) if namespace != notice.namespace() => continue,
+                            _ => json!(notice),
+                        },
+                        Ok(Err(RecvError::Lagged(missed))) => {
+                            json!({ "type": "lagged", "missed": missed })
+                        }
+                        Ok(Err(RecvError::Closed)) => return,
+                        Err(_) => json!({ "type": "heartbeat" }),
+                    };
+
+                if sender.send(Ok(format!("{}\n", line))).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        let res = tide::Response::builder(200)
+            .content_type("application/x-ndjson")
+            .body(tide::Body::from_reader(receiver.into_async_read(), None))
+            .build();
+
+        Ok(res)
+    }
+
+    fn last_event_id(req: &Request<EventRepoPgsql>) -> Option<Position> {
+        let value: &str = req.header("Last-Event-ID")?.last().as_str();
+        match value.parse() {
+            Ok(position) => Some(position),
+            Err(_) => {
+                warn!("Ignoring invalid Last-Event-ID {}", value);
+                None
+            }
+        }
+    }
+
+    fn stream_err(namespace: &str, e: RepoErr) -> tide::Error {
+        error!(namespace = namespace; "Error streaming events, {:?}", e);
+        tide::Error::from_str(500, "Internal Server Error")
+    }
+
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct ChangesQuery {
+        namespace: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct StreamQuery {
+        transitions: Option<bool>,
+    }
+
+    /// Position in the stream, which is the scheduled time and id of the latest due event and the
+    /// sequence number of the last transition that were sent.
+    #[derive(Debug, Clone, Copy)]
+    struct Position {
+        due_at: chrono::DateTime<chrono::Utc>,
+        due_id: uuid::Uuid,
+        transition: i64,
+    }
+
+    impl fmt::Display for Position {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let micros: i64 =
+                self.due_at.timestamp() * 1_000_000 + self.due_at.timestamp_subsec_micros() as i64;
+            write!(
+                f,
+                "{}_{}_{}",
+                micros,
+                self.due_id.to_simple(),
+                self.transition
+            )
+        }
+    }
+
+    impl FromStr for Position {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let parts: Vec<&str> = s.split('_').collect();
+            let (micros, due_id, transition) = match parts.as_slice() {
+                [micros, due_id, transition] => (micros, due_id, transition),
+                _ => return Err(format!("Invalid position {}", s)),
+            };
+
+            let micros: i64 = micros
+                .parse()
+                .map_err(|_| format!("Invalid position {}", s))?;
+            let due_at = chrono::Utc
+                .timestamp_opt(
+                    micros.div_euclid(1_000_000),
+                    micros.rem_euclid(1_000_000) as u32 * 1000,
+                )
+                .single()
+                .ok_or_else(|| format!("Invalid position {}", s))?;
+
+            let position = Position {
+                due_at,
+                due_id: due_id
+                    .parse()
+                    .map_err(|_| format!("Invalid position {}", s))?,
+                transition: transition
+                    .parse()
+                    .map_err(|_| format!("Invalid position {}", s))?,
+            };
+
+            Ok(position)
+        }
+    }
+}
+
+pub mod health {
+    use serde_json::json;
+    use tide::{Middleware, Next, Request};
+
+    use super::event::ok;
+    use crate::health::Readiness;
+
+    /// Answers the liveness and readiness probes of an orchestrator ahead of any route, so they
+    /// are served without going through the event handlers or the middleware after it
+    pub struct Probes {
+        readiness: Readiness,
+        started: chrono::DateTime<chrono::Utc>,
+    }
+
+    impl Probes {
+        pub fn new(readiness: Readiness) -> Probes {
+            Probes {
+                readiness,
+                started: chrono::Utc::now(),
+            }
+        }
+
+        /// The process is alive as long as it answers at all
+        fn alive(&self) -> tide::Result {
+            ok(200, json!({ "alive": true, "started": self.started }))
+        }
+
+        /// Whether the service is ready to serve requests, with the latest check of each
+        /// dependency
+        fn ready(&self) -> tide::Result {
+            let ready: bool = self.readiness.is_ready();
+            let body = json!({ "ready": ready, "checks": self.readiness.checks() });
+            match ready {
+                true => ok(200, body),
+                false => ok(503, body),
+            }
+        }
+    }
+
+    #[tide::utils::async_trait]
+    impl<State: Clone + Send + Sync + 'static> Middleware<State> for Probes {
+        async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+            if req.method() != tide::http::Method::Get {
+                return Ok(next.run(req).await);
+            }
+
+            match req.url().path() {
+                "/healthz" => self.alive(),
+                "/readyz" => self.ready(),
+                _ => Ok(next.run(req).await),
+            }
+        }
+    }
 }
 
 pub mod webhook {}
@@ -0,0 +1,1327 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::config::ImportIcsArgs;
+use crate::db::event::{BatchOp, BatchResult, EventRepoPgsql, RepoErr};
+use crate::event::Event;
+use crate::http::event::{BatchMode, CreateEvent};
+
+const PRODID: &str = "-//timetable//timetable//EN";
+const UID_DOMAIN: &str = "timetable";
+
+/// Upper limit on the number of periods the recurrence rules of a single file are expanded over
+/// together, so that rules with tiny intervals or a start long ago cannot keep an import busy.
+const MAX_PERIODS: i64 = 100_000;
+
+/// Longest iCalendar file, in bytes, that is accepted for an import
+pub const MAX_INPUT_LEN: u64 = 4 * 1024 * 1024;
+
+/// How far ahead occurrences are scheduled when importing, unless told otherwise
+pub const DEFAULT_HORIZON: std::time::Duration = std::time::Duration::from_secs(90 * 24 * 60 * 60);
+
+/// Furthest ahead occurrences can be scheduled when importing
+pub const MAX_HORIZON: std::time::Duration = std::time::Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Upper limit on the number of occurrences scheduled by a single import
+pub const MAX_OCCURRENCES: usize = 10_000;
+
+/// Longest key of an event, in characters
+const MAX_KEY_LEN: usize = 128;
+
+/// Length of the start time, and the slash before it, in the keys of recurring occurrences
+const OCCURRENCE_SUFFIX_LEN: usize = 17;
+
+/// Start of an iCalendar (RFC 5545) calendar of the events in a namespace, which is followed by a
+/// VEVENT for each event and ended by [`calendar_end`], so that a calendar can be written as its
+/// events are read.
+pub fn calendar_start(namespace: &str) -> String {
+    let mut ics = String::new();
+    line(&mut ics, "BEGIN:VCALENDAR");
+    line(&mut ics, "VERSION:2.0");
+    line(&mut ics, &format!("PRODID:{}", PRODID));
+    line(&mut ics, "CALSCALE:GREGORIAN");
+    line(&mut ics, "METHOD:PUBLISH");
+    line(&mut ics, &format!("X-WR-CALNAME:{}", escape(namespace)));
+    ics
+}
+
+/// Render an event as a VEVENT. The UID is derived from the event id, so that calendar clients
+/// update an entry rather than adding a duplicate each time the calendar is fetched.
+pub fn vevent(event: &Event, now: &chrono::DateTime<chrono::Utc>) -> String {
+    let mut ics = String::new();
+    line(&mut ics, "BEGIN:VEVENT");
+    line(&mut ics, &format!("UID:{}@{}", event.id(), UID_DOMAIN));
+    line(&mut ics, &format!("DTSTAMP:{}", timestamp(now)));
+    line(
+        &mut ics,
+        &format!("DTSTART:{}", timestamp(event.schedule_at())),
+    );
+    line(
+        &mut ics,
+        &format!("CREATED:{}", timestamp(event.created_at())),
+    );
+    line(&mut ics, &format!("SEQUENCE:{}", event.version() - 1));
+    line(&mut ics, &format!("SUMMARY:{}", escape(event.key())));
+    line(
+        &mut ics,
+        &format!("DESCRIPTION:{}", escape(&event.value().to_string())),
+    );
+    line(&mut ics, "END:VEVENT");
+    ics
+}
+
+/// End of a calendar started with [`calendar_start`]
+pub fn calendar_end() -> String {
+    let mut ics = String::new();
+    line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+/// Format a timestamp as an iCalendar DATE-TIME in UTC
+fn timestamp(time: &chrono::DateTime<chrono::Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escape a TEXT value
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => (),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Append a content line, folded so that no line is longer than 75 octets
+fn line(ics: &mut String, content: &str) {
+    let mut width: usize = 0;
+    for c in content.chars() {
+        if width + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            width = 1;
+        }
+        ics.push(c);
+        width += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+/// An occurrence of a VEVENT, which is scheduled as an event with a key derived from the UID.
+/// Occurrences of recurring VEVENTs also include the original start time of the occurrence in
+/// their key, since there can only be one scheduled event per key. UIDs that are too long for a
+/// key are shortened.
+#[derive(Debug, Clone)]
+pub struct Occurrence {
+    uid: String,
+    key: String,
+    scheduled_at: chrono::DateTime<Utc>,
+    value: serde_json::Value,
+}
+
+impl Occurrence {
+    pub fn uid(&self) -> &str {
+        &self.uid
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn scheduled_at(&self) -> chrono::DateTime<Utc> {
+        self.scheduled_at
+    }
+}
+
+/// A VEVENT that could not be converted to events
+#[derive(Debug, Clone)]
+pub struct Invalid {
+    uid: Option<String>,
+    error: String,
+}
+
+impl Invalid {
+    pub fn uid(&self) -> Option<&str> {
+        self.uid.as_deref()
+    }
+
+    pub fn error(&self) -> &str {
+        &self.error
+    }
+}
+
+/// Reasons an iCalendar file cannot be imported at all
+#[derive(Debug)]
+pub enum ImportErr {
+    /// The import was rejected before anything was scheduled, such as for a horizon that is too
+    /// far ahead or a file with too many occurrences
+    Rejected(String),
+    Repo(RepoErr),
+}
+
+impl From<RepoErr> for ImportErr {
+    fn from(e: RepoErr) -> Self {
+        ImportErr::Repo(e)
+    }
+}
+
+/// Outcome of importing a single item from an iCalendar file
+#[allow(clippy::large_enum_variant)]
+pub enum ImportItem {
+    Invalid(Invalid),
+    Parsed(Occurrence, BatchResult),
+}
+
+/// Schedule an event in the namespace for each occurrence, from now and within the horizon, of the
+/// VEVENTs in an iCalendar file. Occurrences that cannot be scheduled, such as when there already
+/// is a scheduled event with the same key, are reported without affecting the other occurrences.
+/// On a dry run, the outcome is reported as if the occurrences were scheduled, but nothing is
+/// changed. Imports with a horizon beyond [`MAX_HORIZON`], with more than [`MAX_OCCURRENCES`]
+/// occurrences or with recurrence rules that take too long to expand are rejected.
+pub async fn import(
+    repo: &EventRepoPgsql,
+    namespace: &str,
+    input: &str,
+    horizon: std::time::Duration,
+    dry_run: bool,
+    caller: Option<&str>,
+) -> Result<Vec<ImportItem>, ImportErr> {
+    if horizon > MAX_HORIZON {
+        return Err(ImportErr::Rejected(String::from(
+            "Horizon is longer than 365 days",
+        )));
+    }
+
+    let now: chrono::DateTime<Utc> = Utc::now();
+    let until: chrono::DateTime<Utc> = match chrono::Duration::from_std(horizon)
+        .ok()
+        .and_then(|horizon| now.checked_add_signed(horizon))
+    {
+        Some(until) => until,
+        None => return Err(ImportErr::Rejected(String::from("Horizon is out of range"))),
+    };
+
+    let parsed: Vec<Result<Occurrence, Invalid>> =
+        occurrences(input, now, until).map_err(ImportErr::Rejected)?;
+
+    let events: Vec<CreateEvent> = parsed
+        .iter()
+        .filter_map(|occurrence| occurrence.as_ref().ok())
+        .map(|occurrence| {
+            CreateEvent::new(
+                occurrence.key.clone(),
+                occurrence.value.clone(),
+                namespace.to_string(),
+                occurrence.scheduled_at,
+            )
+        })
+        .collect();
+
+    let ops: Vec<BatchOp> = events.iter().map(BatchOp::Create).collect();
+    let results: Vec<BatchResult> = match dry_run {
+        true => repo.preview_batch(&ops, caller).await?,
+        false => repo.batch(&ops, BatchMode::Partial, caller).await?,
+    };
+
+    let mut results = results.into_iter();
+    let items: Vec<ImportItem> = parsed
+        .into_iter()
+        .map(|occurrence| match occurrence {
+            Ok(occurrence) => match results.next() {
+                Some(res) => ImportItem::Parsed(occurrence, res),
+                None => ImportItem::Parsed(occurrence, Err(RepoErr::NoResult)),
+            },
+            Err(invalid) => ImportItem::Invalid(invalid),
+        })
+        .collect();
+
+    Ok(items)
+}
+
+/// Import an iCalendar file from the command line, printing the outcome for each occurrence.
+/// Fails if any occurrence could not be imported or any VEVENT was invalid, after importing all
+/// others.
+pub async fn import_cmd(repo: &EventRepoPgsql, args: &ImportIcsArgs) -> Result<(), RepoErr> {
+    let mut input = String::new();
+    let (res, source) = match args.input() {
+        Some(path) => match File::open(path) {
+            Ok(mut file) => (file.read_to_string(&mut input), path.display().to_string()),
+            Err(e) => return Err(RepoErr::Other(format!("{}: {}", path.display(), e))),
+        },
+        None => (
+            io::stdin().read_to_string(&mut input),
+            String::from("stdin"),
+        ),
+    };
+
+    if let Err(e) = res {
+        return Err(RepoErr::Other(format!("{}: {}", source, e)));
+    }
+
+    let items: Vec<ImportItem> = import(
+        repo,
+        args.namespace(),
+        &input,
+        args.horizon(),
+        args.dry_run(),
+        None,
+    )
+    .await
+    .map_err(|e| match e {
+        ImportErr::Rejected(reason) => RepoErr::Other(format!("{}: {}", source, reason)),
+        ImportErr::Repo(e) => e,
+    })?;
+
+    let (mut scheduled, mut conflicts, mut failed) = (0, 0, 0);
+    for item in items {
+        match item {
+            ImportItem::Parsed(occurrence, Ok(_)) => {
+                scheduled += 1;
+                println!(
+                    "{} at {}: scheduled",
+                    occurrence.key,
+                    occurrence.scheduled_at.to_rfc3339()
+                );
+            }
+            ImportItem::Parsed(occurrence, Err(RepoErr::AlreadyScheduled)) => {
+                conflicts += 1;
+                println!(
+                    "{} at {}: already scheduled",
+                    occurrence.key,
+                    occurrence.scheduled_at.to_rfc3339()
+                );
+            }
+            ImportItem::Parsed(occurrence, Err(e)) => {
+                failed += 1;
+                println!(
+                    "{} at {}: failed, {:?}",
+                    occurrence.key,
+                    occurrence.scheduled_at.to_rfc3339(),
+                    e
+                );
+            }
+            ImportItem::Invalid(invalid) => {
+                failed += 1;
+                println!(
+                    "{}: invalid, {}",
+                    invalid.uid.as_deref().unwrap_or("VEVENT without UID"),
+                    invalid.error
+                );
+            }
+        }
+    }
+
+    let verb: &str = match args.dry_run() {
+        true => "would schedule",
+        false => "scheduled",
+    };
+
+    println!(
+        "{}: {} {} events, {} already scheduled, {} failed",
+        source, verb, scheduled, conflicts, failed
+    );
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(RepoErr::Other(format!(
+            "{}: {} occurrences failed to import",
+            source, failed
+        ))),
+    }
+}
+
+/// Occurrences of all VEVENTs in an iCalendar file that start within the given period. A VEVENT
+/// with a RECURRENCE-ID replaces the occurrence of the recurring VEVENT with the same UID that
+/// would otherwise have started at that time. Files with more than [`MAX_OCCURRENCES`]
+/// occurrences, or with recurrence rules that take more than [`MAX_PERIODS`] periods to expand,
+/// are rejected as soon as either limit is reached.
+pub fn occurrences(
+    input: &str,
+    from: chrono::DateTime<Utc>,
+    until: chrono::DateTime<Utc>,
+) -> Result<Vec<Result<Occurrence, Invalid>>, String> {
+    let vevents: Vec<Result<VEvent, Invalid>> = components(input)
+        .iter()
+        .map(|props| VEvent::parse(props))
+        .collect();
+
+    let overrides: HashSet<(String, chrono::DateTime<Utc>)> = vevents
+        .iter()
+        .filter_map(|vevent| vevent.as_ref().ok())
+        .filter_map(|vevent| Some((vevent.uid.clone(), vevent.recurrence_id?)))
+        .collect();
+
+    let mut occurrences: Vec<Result<Occurrence, Invalid>> = Vec::new();
+    let mut scheduled: usize = 0;
+    let mut periods: i64 = MAX_PERIODS;
+    for vevent in vevents {
+        let vevent: VEvent = match vevent {
+            Ok(vevent) => vevent,
+            Err(invalid) => {
+                occurrences.push(Err(invalid));
+                continue;
+            }
+        };
+
+        if vevent.cancelled {
+            continue;
+        }
+
+        let start: chrono::DateTime<Utc> = match vevent.start.utc() {
+            Some(start) => start,
+            None => {
+                occurrences.push(Err(
+                    vevent.invalid("DTSTART does not exist in its time zone")
+                ));
+                continue;
+            }
+        };
+
+        let times: Vec<(String, chrono::DateTime<Utc>)> = match (&vevent.rule, vevent.recurrence_id)
+        {
+            (_, Some(recurrence_id)) => vec![(vevent.occurrence_key(recurrence_id), start)],
+            (Some(rule), None) => rule
+                .expand(vevent.start, from, until, &mut periods)?
+                .into_iter()
+                .filter(|time| !vevent.exdates.contains(time))
+                .filter(|time| !overrides.contains(&(vevent.uid.clone(), *time)))
+                .map(|time| (vevent.occurrence_key(time), time))
+                .collect(),
+            (None, None) => vec![(vevent.key(), start)],
+        };
+
+        for (key, time) in times {
+            if time < from || time >= until {
+                continue;
+            }
+
+            scheduled += 1;
+            if scheduled > MAX_OCCURRENCES {
+                return Err(format!(
+                    "More than {} occurrences within the horizon",
+                    MAX_OCCURRENCES
+                ));
+            }
+
+            occurrences.push(Ok(Occurrence {
+                uid: vevent.uid.clone(),
+                key,
+                scheduled_at: time,
+                value: vevent.value(),
+            }));
+        }
+    }
+
+    Ok(occurrences)
+}
+
+/// A content line of an iCalendar file
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Property> {
+        let mut quoted: bool = false;
+        let mut split: Option<usize> = None;
+        for (i, c) in line.char_indices() {
+            match c {
+                '"' => quoted = !quoted,
+                ':' if !quoted => {
+                    split = Some(i);
+                    break;
+                }
+                _ => (),
+            }
+        }
+
+        let (head, value) = line.split_at(split?);
+        let mut parts = head.split(';');
+        let name: String = parts.next()?.to_ascii_uppercase();
+        let params: Vec<(String, String)> = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(k, v)| (k.to_ascii_uppercase(), v.trim_matches('"').to_string()))
+            .collect();
+
+        Some(Property {
+            name,
+            params,
+            value: value[1..].to_string(),
+        })
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn zone(&self) -> Result<Option<Tz>, String> {
+        match self.param("TZID") {
+            Some(id) => match id.parse::<Tz>() {
+                Ok(tz) => Ok(Some(tz)),
+                Err(_) => Err(format!("Unknown time zone {}", id)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn times(&self) -> Result<Vec<Time>, String> {
+        let zone: Option<Tz> = self.zone()?;
+        self.value
+            .split(',')
+            .map(|value| Time::parse(value.trim(), zone))
+            .collect()
+    }
+}
+
+/// The properties of each top level VEVENT in an iCalendar file. Nested components, such as
+/// VALARMs, are left out.
+fn components(input: &str) -> Vec<Vec<Property>> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in input.lines() {
+        let line: &str = line.trim_end_matches('\r');
+        match (
+            line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')),
+            lines.last_mut(),
+        ) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut components: Vec<Vec<Property>> = Vec::new();
+    let mut current: Option<Vec<Property>> = None;
+    let mut nested: usize = 0;
+    for prop in lines.iter().filter_map(|line| Property::parse(line)) {
+        let value: String = prop.value.to_ascii_uppercase();
+        match (prop.name.as_str(), &mut current) {
+            ("BEGIN", None) if value == "VEVENT" => current = Some(Vec::new()),
+            ("BEGIN", Some(_)) => nested += 1,
+            ("END", Some(_)) if nested > 0 => nested -= 1,
+            ("END", Some(_)) if value == "VEVENT" => components.extend(current.take()),
+            (_, Some(props)) if nested == 0 => props.push(prop),
+            _ => (),
+        }
+    }
+
+    components
+}
+
+/// A local date and time, in a time zone or in UTC
+#[derive(Debug, Clone, Copy)]
+struct Time {
+    local: NaiveDateTime,
+    zone: Option<Tz>,
+}
+
+impl Time {
+    /// Parse a DATE-TIME or a DATE. Times without a time zone, which are meant to be in the local
+    /// time of whoever is looking at the calendar, are taken to be in UTC.
+    fn parse(value: &str, zone: Option<Tz>) -> Result<Time, String> {
+        let (value, zone) = match value.strip_suffix('Z') {
+            Some(value) => (value, None),
+            None => (value, zone),
+        };
+
+        let local = match value.len() {
+            8 => NaiveDate::parse_from_str(value, "%Y%m%d").map(|date| date.and_hms(0, 0, 0)),
+            _ => NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S"),
+        };
+
+        match local {
+            Ok(local) => Ok(Time { local, zone }),
+            Err(_) => Err(format!("Invalid date or time {}", value)),
+        }
+    }
+
+    fn utc(&self) -> Option<chrono::DateTime<Utc>> {
+        match self.zone {
+            Some(tz) => tz
+                .from_local_datetime(&self.local)
+                .earliest()
+                .map(|time| time.with_timezone(&Utc)),
+            None => Some(Utc.from_utc_datetime(&self.local)),
+        }
+    }
+
+    fn with_local(&self, local: NaiveDateTime) -> Time {
+        Time {
+            local,
+            zone: self.zone,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A recurrence rule. Only the most common parts of RRULE are supported, and a rule with any other
+/// part is rejected rather than expanded into the wrong occurrences.
+#[derive(Debug, Clone)]
+struct Rule {
+    frequency: Frequency,
+    interval: i64,
+    count: Option<usize>,
+    until: Option<chrono::DateTime<Utc>>,
+    by_day: Vec<chrono::Weekday>,
+}
+
+impl Rule {
+    fn parse(value: &str, zone: Option<Tz>) -> Result<Rule, String> {
+        let mut rule = Rule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+        };
+        let mut frequency: Option<Frequency> = None;
+
+        for part in value.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = match part.split_once('=') {
+                Some((name, value)) => (name.to_ascii_uppercase(), value),
+                None => return Err(format!("Invalid RRULE part {}", part)),
+            };
+
+            match name.as_str() {
+                "FREQ" => {
+                    frequency = match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Some(Frequency::Daily),
+                        "WEEKLY" => Some(Frequency::Weekly),
+                        "MONTHLY" => Some(Frequency::Monthly),
+                        "YEARLY" => Some(Frequency::Yearly),
+                        _ => return Err(format!("Unsupported RRULE frequency {}", value)),
+                    }
+                }
+                "INTERVAL" => match value.parse::<i64>() {
+                    Ok(interval) if interval > 0 => rule.interval = interval,
+                    _ => return Err(format!("Invalid RRULE interval {}", value)),
+                },
+                "COUNT" => match value.parse::<usize>() {
+                    Ok(count) => rule.count = Some(count),
+                    Err(_) => return Err(format!("Invalid RRULE count {}", value)),
+                },
+                "UNTIL" => {
+                    let mut until: Time = Time::parse(value, zone)?;
+                    if value.len() == 8 {
+                        until.local = until.local.date().and_hms(23, 59, 59);
+                    }
+                    rule.until = until.utc();
+                }
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        match weekday(day) {
+                            Some(day) => rule.by_day.push(day),
+                            None => return Err(format!("Unsupported RRULE BYDAY {}", day)),
+                        }
+                    }
+                }
+                "WKST" => (),
+                _ => return Err(format!("Unsupported RRULE part {}", name)),
+            }
+        }
+
+        rule.frequency = match frequency {
+            Some(frequency) => frequency,
+            None => return Err(String::from("RRULE without FREQ")),
+        };
+
+        if !rule.by_day.is_empty() && !matches!(rule.frequency, Frequency::Weekly) {
+            return Err(String::from(
+                "RRULE BYDAY is only supported with FREQ=WEEKLY",
+            ));
+        }
+
+        rule.by_day.sort_by_key(|day| day.num_days_from_monday());
+
+        Ok(rule)
+    }
+
+    /// Start times of the occurrences from the given time, and before the given time or the end of
+    /// the rule, whichever comes first. Unless the rule has a COUNT, which includes the occurrences
+    /// before `from`, the periods before `from` are skipped rather than expanded. Each period that
+    /// is expanded is taken from `periods`, and the rule is rejected once none are left.
+    fn expand(
+        &self,
+        start: Time,
+        from: chrono::DateTime<Utc>,
+        before: chrono::DateTime<Utc>,
+        periods: &mut i64,
+    ) -> Result<Vec<chrono::DateTime<Utc>>, String> {
+        let mut times: Vec<chrono::DateTime<Utc>> = Vec::new();
+        let mut count: usize = 0;
+
+        let first: i64 = match self.count {
+            Some(_) => 0,
+            None => self.periods_before(start, from),
+        };
+
+        for period in first.. {
+            if *periods <= 0 {
+                return Err(format!(
+                    "Recurrence rules take more than {} periods to expand",
+                    MAX_PERIODS
+                ));
+            }
+            *periods -= 1;
+
+            let n: i64 = period * self.interval;
+            let candidates: Vec<NaiveDateTime> = match self.frequency {
+                Frequency::Daily => vec![start.local + chrono::Duration::days(n)],
+                Frequency::Weekly if self.by_day.is_empty() => {
+                    vec![start.local + chrono::Duration::weeks(n)]
+                }
+                Frequency::Weekly => {
+                    let offset = start.local.weekday().num_days_from_monday() as i64;
+                    let monday = start.local - chrono::Duration::days(offset);
+                    self.by_day
+                        .iter()
+                        .map(|day| {
+                            let days = day.num_days_from_monday() as i64;
+                            monday + chrono::Duration::weeks(n) + chrono::Duration::days(days)
+                        })
+                        .filter(|local| *local >= start.local)
+                        .collect()
+                }
+                Frequency::Monthly => add_months(start.local, n).into_iter().collect(),
+                Frequency::Yearly => add_months(start.local, 12 * n).into_iter().collect(),
+            };
+
+            for local in candidates {
+                let time: chrono::DateTime<Utc> = match start.with_local(local).utc() {
+                    Some(time) => time,
+                    None => continue,
+                };
+
+                let ended: bool = self.until.is_some_and(|until| time > until)
+                    || self.count.is_some_and(|max| count >= max);
+
+                if ended || time >= before {
+                    return Ok(times);
+                }
+
+                count += 1;
+                if time >= from {
+                    times.push(time);
+                }
+            }
+        }
+
+        Ok(times)
+    }
+
+    /// Number of whole periods from the start that certainly end before the given time. One
+    /// period is left out, so that the difference between local time and UTC cannot cause an
+    /// occurrence to be skipped.
+    fn periods_before(&self, start: Time, time: chrono::DateTime<Utc>) -> i64 {
+        let time: NaiveDateTime = time.naive_utc();
+        let units: i64 = match self.frequency {
+            Frequency::Daily => (time - start.local).num_days(),
+            Frequency::Weekly => (time - start.local).num_weeks(),
+            Frequency::Monthly => months_between(start.local, time),
+            Frequency::Yearly => months_between(start.local, time) / 12,
+        };
+
+        (units / self.interval - 1).max(0)
+    }
+}
+
+/// Number of calendar months from one time to another, ignoring the day of the month
+fn months_between(from: NaiveDateTime, to: NaiveDateTime) -> i64 {
+    (to.year() as i64 * 12 + to.month0() as i64) - (from.year() as i64 * 12 + from.month0() as i64)
+}
+
+/// The same day and time a number of months later, unless that month has no such day
+fn add_months(local: NaiveDateTime, months: i64) -> Option<NaiveDateTime> {
+    let month: i64 = local.year() as i64 * 12 + local.month0() as i64 + months;
+    let date = NaiveDate::from_ymd_opt((month / 12) as i32, (month % 12) as u32 + 1, local.day())?;
+    Some(date.and_time(local.time()))
+}
+
+fn weekday(day: &str) -> Option<chrono::Weekday> {
+    match day.to_ascii_uppercase().as_str() {
+        "MO" => Some(chrono::Weekday::Mon),
+        "TU" => Some(chrono::Weekday::Tue),
+        "WE" => Some(chrono::Weekday::Wed),
+        "TH" => Some(chrono::Weekday::Thu),
+        "FR" => Some(chrono::Weekday::Fri),
+        "SA" => Some(chrono::Weekday::Sat),
+        "SU" => Some(chrono::Weekday::Sun),
+        _ => None,
+    }
+}
+
+struct VEvent {
+    uid: String,
+    summary: Option<String>,
+    description: Option<String>,
+    location: Option<String>,
+    start: Time,
+    rule: Option<Rule>,
+    exdates: HashSet<chrono::DateTime<Utc>>,
+    recurrence_id: Option<chrono::DateTime<Utc>>,
+    cancelled: bool,
+}
+
+impl VEvent {
+    fn parse(props: &[Property]) -> Result<VEvent, Invalid> {
+        let uid: Option<String> = props
+            .iter()
+            .find(|prop| prop.name == "UID")
+            .map(|prop| unescape(&prop.value));
+
+        let invalid = |error: String| Invalid {
+            uid: uid.clone(),
+            error,
+        };
+
+        let uid: String = match &uid {
+            Some(uid) if !uid.is_empty() => uid.clone(),
+            _ => return Err(invalid(String::from("VEVENT without UID"))),
+        };
+
+        let mut start: Option<Time> = None;
+        let mut rule: Option<&str> = None;
+        let mut exdates: HashSet<chrono::DateTime<Utc>> = HashSet::new();
+        let mut recurrence_id: Option<chrono::DateTime<Utc>> = None;
+        let mut summary: Option<String> = None;
+        let mut description: Option<String> = None;
+        let mut location: Option<String> = None;
+        let mut cancelled: bool = false;
+
+        for prop in props {
+            match prop.name.as_str() {
+                "DTSTART" => start = prop.times().map_err(invalid)?.first().copied(),
+                "RRULE" => rule = Some(&prop.value),
+                "EXDATE" => {
+                    for time in prop.times().map_err(invalid)? {
+                        exdates.extend(time.utc());
+                    }
+                }
+                "RECURRENCE-ID" => {
+                    recurrence_id = prop.times().map_err(invalid)?.first().and_then(Time::utc)
+                }
+                "RDATE" => return Err(invalid(String::from("RDATE is not supported"))),
+                "SUMMARY" => summary = Some(unescape(&prop.value)),
+                "DESCRIPTION" => description = Some(unescape(&prop.value)),
+                "LOCATION" => location = Some(unescape(&prop.value)),
+                "STATUS" => cancelled = prop.value.eq_ignore_ascii_case("CANCELLED"),
+                _ => (),
+            }
+        }
+
+        let start: Time = match start {
+            Some(start) => start,
+            None => return Err(invalid(String::from("VEVENT without DTSTART"))),
+        };
+
+        let rule: Option<Rule> = match rule {
+            Some(rule) => Some(Rule::parse(rule, start.zone).map_err(invalid)?),
+            None => None,
+        };
+
+        Ok(VEvent {
+            uid,
+            summary,
+            description,
+            location,
+            start,
+            rule,
+            exdates,
+            recurrence_id,
+            cancelled,
+        })
+    }
+
+    fn invalid(&self, error: &str) -> Invalid {
+        Invalid {
+            uid: Some(self.uid.clone()),
+            error: error.to_string(),
+        }
+    }
+
+    /// The UID, unless it is too long to fit in the key of an occurrence together with its start
+    /// time, in which case it is cut short and followed by a hash of the whole UID, so that
+    /// different UIDs still get different keys.
+    fn key(&self) -> String {
+        let max_len: usize = MAX_KEY_LEN - OCCURRENCE_SUFFIX_LEN;
+        if self.uid.chars().count() <= max_len {
+            return self.uid.clone();
+        }
+
+        let hash = uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_OID, self.uid.as_bytes());
+        let hash: String = hash.to_simple().to_string();
+        let prefix: String = self.uid.chars().take(max_len - hash.len() - 1).collect();
+        format!("{}#{}", prefix, hash)
+    }
+
+    fn occurrence_key(&self, time: chrono::DateTime<Utc>) -> String {
+        format!("{}/{}", self.key(), timestamp(&time))
+    }
+
+    fn value(&self) -> serde_json::Value {
+        let mut value = serde_json::Map::new();
+        value.insert(String::from("uid"), self.uid.clone().into());
+        let fields = [
+            ("summary", &self.summary),
+            ("description", &self.description),
+            ("location", &self.location),
+        ];
+        for (name, field) in fields {
+            if let Some(field) = field {
+                value.insert(String::from(name), field.clone().into());
+            }
+        }
+
+        serde_json::Value::Object(value)
+    }
+}
+
+/// Reverse the escaping of a TEXT value
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(time: &str) -> chrono::DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn ics(vevents: &[&str]) -> String {
+        let mut ics = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n");
+        for vevent in vevents {
+            ics.push_str("BEGIN:VEVENT\r\n");
+            ics.push_str(vevent);
+            ics.push_str("END:VEVENT\r\n");
+        }
+        ics.push_str("END:VCALENDAR\r\n");
+        ics
+    }
+
+    fn parse(vevents: &[&str]) -> Vec<Result<Occurrence, Invalid>> {
+        occurrences(
+            &ics(vevents),
+            utc("2026-01-01T00:00:00Z"),
+            utc("2027-01-01T00:00:00Z"),
+        )
+        .unwrap()
+    }
+
+    fn times(vevents: &[&str]) -> Vec<(String, chrono::DateTime<Utc>)> {
+        parse(vevents)
+            .into_iter()
+            .map(|occurrence| occurrence.map_err(|invalid| invalid.error).unwrap())
+            .map(|occurrence| (occurrence.key, occurrence.scheduled_at))
+            .collect()
+    }
+
+    fn errors(vevents: &[&str]) -> Vec<String> {
+        parse(vevents)
+            .into_iter()
+            .filter_map(|occurrence| occurrence.err())
+            .map(|invalid| invalid.error)
+            .collect()
+    }
+
+    #[test]
+    fn single_event() {
+        assert_eq!(
+            times(&["UID:a\r\nDTSTART:20260301T090000Z\r\n"]),
+            vec![(String::from("a"), utc("2026-03-01T09:00:00Z"))]
+        );
+    }
+
+    #[test]
+    fn outside_of_period() {
+        let vevents = [
+            "UID:a\r\nDTSTART:20251231T235959Z\r\n",
+            "UID:b\r\nDTSTART:20270101T000000Z\r\n",
+            "UID:c\r\nDTSTART:20260101T000000Z\r\n",
+        ];
+        assert_eq!(
+            times(&vevents),
+            vec![(String::from("c"), utc("2026-01-01T00:00:00Z"))]
+        );
+    }
+
+    #[test]
+    fn unfolded_and_unescaped() {
+        let occurrences = parse(&[concat!(
+            "UID:a\r\n",
+            "DTSTART:20260301T090000Z\r\n",
+            "SUMMARY:Stand\r\n",
+            " up\\, daily\\; \r\n",
+            "\tor not\r\n",
+            "DESCRIPTION:First\\nsecond\\Nthird \\\\ back\\\\slash\r\n",
+            "BEGIN:VALARM\r\n",
+            "DESCRIPTION:Reminder\r\n",
+            "END:VALARM\r\n",
+        )]);
+
+        let occurrence = occurrences[0].as_ref().map_err(|e| &e.error).unwrap();
+        assert_eq!(
+            occurrence.value,
+            serde_json::json!({
+                "uid": "a",
+                "summary": "Standup, daily; or not",
+                "description": "First\nsecond\nthird \\ back\\slash",
+            })
+        );
+    }
+
+    #[test]
+    fn time_zone() {
+        let vevents = [
+            "UID:a\r\nDTSTART;TZID=Europe/Amsterdam:20260301T090000\r\n",
+            "UID:b\r\nDTSTART;TZID=\"America/New_York\":20260701T090000\r\n",
+            "UID:c\r\nDTSTART:20260301T090000\r\n",
+            "UID:d\r\nDTSTART;VALUE=DATE:20260301\r\n",
+        ];
+        assert_eq!(
+            times(&vevents),
+            vec![
+                (String::from("a"), utc("2026-03-01T08:00:00Z")),
+                (String::from("b"), utc("2026-07-01T13:00:00Z")),
+                (String::from("c"), utc("2026-03-01T09:00:00Z")),
+                (String::from("d"), utc("2026-03-01T00:00:00Z")),
+            ]
+        );
+    }
+
+    #[test]
+    fn recurrence_keeps_local_time() {
+        let vevent = concat!(
+            "UID:a\r\n",
+            "DTSTART;TZID=Europe/Amsterdam:20260322T090000\r\n",
+            "RRULE:FREQ=WEEKLY;COUNT=2\r\n",
+        );
+        assert_eq!(
+            times(&[vevent]),
+            vec![
+                (
+                    String::from("a/20260322T080000Z"),
+                    utc("2026-03-22T08:00:00Z")
+                ),
+                (
+                    String::from("a/20260329T070000Z"),
+                    utc("2026-03-29T07:00:00Z")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn daily_interval_count() {
+        let vevent = "UID:a\r\nDTSTART:20260301T090000Z\r\nRRULE:FREQ=DAILY;INTERVAL=2;COUNT=3\r\n";
+        let times: Vec<chrono::DateTime<Utc>> =
+            times(&[vevent]).into_iter().map(|(_, time)| time).collect();
+        assert_eq!(
+            times,
+            vec![
+                utc("2026-03-01T09:00:00Z"),
+                utc("2026-03-03T09:00:00Z"),
+                utc("2026-03-05T09:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn count_includes_past_occurrences() {
+        let vevent = "UID:a\r\nDTSTART:20251230T090000Z\r\nRRULE:FREQ=DAILY;COUNT=4\r\n";
+        let times: Vec<chrono::DateTime<Utc>> =
+            times(&[vevent]).into_iter().map(|(_, time)| time).collect();
+        assert_eq!(
+            times,
+            vec![utc("2026-01-01T09:00:00Z"), utc("2026-01-02T09:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn start_long_ago() {
+        let vevents = [
+            "UID:a\r\nDTSTART:17000101T090000Z\r\nRRULE:FREQ=DAILY;INTERVAL=3\r\n",
+            "UID:b\r\nDTSTART;TZID=Pacific/Kiritimati:17000101T010000\r\nRRULE:FREQ=DAILY\r\n",
+            "UID:c\r\nDTSTART:17000131T090000Z\r\nRRULE:FREQ=MONTHLY\r\n",
+        ];
+        let times: Vec<(String, chrono::DateTime<Utc>)> = times(&vevents);
+        let first = |uid: &str| {
+            times
+                .iter()
+                .find(|(key, _)| key.starts_with(uid))
+                .unwrap()
+                .1
+        };
+
+        assert_eq!(first("a/"), utc("2026-01-02T09:00:00Z"));
+        assert_eq!(first("b/"), utc("2026-01-01T11:00:00Z"));
+        assert_eq!(first("c/"), utc("2026-01-31T09:00:00Z"));
+        assert_eq!(times.len(), 122 + 365 + 7);
+    }
+
+    #[test]
+    fn too_many_occurrences() {
+        let vevent = "UID:a\r\nDTSTART:20260101T000000Z\r\nRRULE:FREQ=DAILY\r\n";
+        let vevents: Vec<&str> = vec![vevent; MAX_OCCURRENCES / 365 + 1];
+        let res = occurrences(
+            &ics(&vevents),
+            utc("2026-01-01T00:00:00Z"),
+            utc("2027-01-01T00:00:00Z"),
+        );
+
+        assert!(res.unwrap_err().starts_with("More than"));
+    }
+
+    #[test]
+    fn too_many_periods() {
+        let vevent = "UID:a\r\nDTSTART:17000101T090000Z\r\nRRULE:FREQ=DAILY;COUNT=1000000\r\n";
+        let res = occurrences(
+            &ics(&[vevent]),
+            utc("2026-01-01T00:00:00Z"),
+            utc("2027-01-01T00:00:00Z"),
+        );
+
+        assert!(res
+            .unwrap_err()
+            .starts_with("Recurrence rules take more than"));
+    }
+
+    #[test]
+    fn until() {
+        let vevents = [
+            "UID:a\r\nDTSTART:20260301T090000Z\r\nRRULE:FREQ=DAILY;UNTIL=20260303T090000Z\r\n",
+            "UID:b\r\nDTSTART:20260301T090000Z\r\nRRULE:FREQ=DAILY;UNTIL=20260302\r\n",
+        ];
+        let times: Vec<chrono::DateTime<Utc>> =
+            times(&vevents).into_iter().map(|(_, time)| time).collect();
+        assert_eq!(
+            times,
+            vec![
+                utc("2026-03-01T09:00:00Z"),
+                utc("2026-03-02T09:00:00Z"),
+                utc("2026-03-03T09:00:00Z"),
+                utc("2026-03-01T09:00:00Z"),
+                utc("2026-03-02T09:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn weekly_by_day() {
+        let vevent = concat!(
+            "UID:a\r\n",
+            "DTSTART:20260304T090000Z\r\n",
+            "RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=FR,MO,WE;COUNT=5;WKST=MO\r\n",
+        );
+        let times: Vec<chrono::DateTime<Utc>> =
+            times(&[vevent]).into_iter().map(|(_, time)| time).collect();
+        assert_eq!(
+            times,
+            vec![
+                utc("2026-03-04T09:00:00Z"),
+                utc("2026-03-06T09:00:00Z"),
+                utc("2026-03-16T09:00:00Z"),
+                utc("2026-03-18T09:00:00Z"),
+                utc("2026-03-20T09:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn monthly_skips_missing_days() {
+        let vevent = "UID:a\r\nDTSTART:20260131T090000Z\r\nRRULE:FREQ=MONTHLY;COUNT=4\r\n";
+        let times: Vec<chrono::DateTime<Utc>> =
+            times(&[vevent]).into_iter().map(|(_, time)| time).collect();
+        assert_eq!(
+            times,
+            vec![
+                utc("2026-01-31T09:00:00Z"),
+                utc("2026-03-31T09:00:00Z"),
+                utc("2026-05-31T09:00:00Z"),
+                utc("2026-07-31T09:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn yearly() {
+        let vevent = "UID:a\r\nDTSTART:20240229T090000Z\r\nRRULE:FREQ=YEARLY\r\n";
+        assert!(times(&[vevent]).is_empty());
+
+        let vevent = "UID:a\r\nDTSTART:20250615T090000Z\r\nRRULE:FREQ=YEARLY\r\n";
+        assert_eq!(
+            times(&[vevent]),
+            vec![(
+                String::from("a/20260615T090000Z"),
+                utc("2026-06-15T09:00:00Z")
+            )]
+        );
+    }
+
+    #[test]
+    fn exdate() {
+        let vevent = concat!(
+            "UID:a\r\n",
+            "DTSTART;TZID=Europe/Amsterdam:20260301T090000\r\n",
+            "RRULE:FREQ=DAILY;COUNT=4\r\n",
+            "EXDATE;TZID=Europe/Amsterdam:20260302T090000,20260303T090000\r\n",
+        );
+        let times: Vec<chrono::DateTime<Utc>> =
+            times(&[vevent]).into_iter().map(|(_, time)| time).collect();
+        assert_eq!(
+            times,
+            vec![utc("2026-03-01T08:00:00Z"), utc("2026-03-04T08:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn recurrence_id_overrides() {
+        let vevents = [
+            "UID:a\r\nDTSTART:20260301T090000Z\r\nRRULE:FREQ=DAILY;COUNT=3\r\n",
+            "UID:a\r\nRECURRENCE-ID:20260302T090000Z\r\nDTSTART:20260302T150000Z\r\n",
+            "UID:a\r\nRECURRENCE-ID:20260303T090000Z\r\nDTSTART:20260303T090000Z\r\nSTATUS:CANCELLED\r\n",
+        ];
+        assert_eq!(
+            times(&vevents),
+            vec![
+                (
+                    String::from("a/20260301T090000Z"),
+                    utc("2026-03-01T09:00:00Z")
+                ),
+                (
+                    String::from("a/20260302T090000Z"),
+                    utc("2026-03-02T15:00:00Z")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled() {
+        let vevent = "UID:a\r\nDTSTART:20260301T090000Z\r\nSTATUS:CANCELLED\r\n";
+        assert!(times(&[vevent]).is_empty());
+    }
+
+    #[test]
+    fn long_uid() {
+        let uid: String = "u".repeat(200);
+        let other: String = format!("{}v", "u".repeat(199));
+        let vevents = [
+            format!("UID:{}\r\nDTSTART:20260301T090000Z\r\n", uid),
+            format!(
+                "UID:{}\r\nDTSTART:20260301T090000Z\r\nRRULE:FREQ=DAILY;COUNT=1\r\n",
+                uid
+            ),
+            format!("UID:{}\r\nDTSTART:20260301T090000Z\r\n", other),
+        ];
+        let vevents: Vec<&str> = vevents.iter().map(String::as_str).collect();
+        let keys: Vec<String> = times(&vevents).into_iter().map(|(key, _)| key).collect();
+
+        assert!(keys.iter().all(|key| key.chars().count() <= MAX_KEY_LEN));
+        assert!(keys[0].starts_with("uuuu"));
+        assert_eq!(keys[1], format!("{}/20260301T090000Z", keys[0]));
+        assert_ne!(keys[0], keys[2]);
+        assert_eq!(
+            keys,
+            times(&vevents)
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let vevents = [
+            "DTSTART:20260301T090000Z\r\n",
+            "UID:a\r\n",
+            "UID:b\r\nDTSTART:2026-03-01\r\n",
+            "UID:c\r\nDTSTART;TZID=Mars/Olympus_Mons:20260301T090000\r\n",
+            "UID:d\r\nDTSTART:20260301T090000Z\r\nRRULE:FREQ=HOURLY\r\n",
+            "UID:e\r\nDTSTART:20260301T090000Z\r\nRRULE:FREQ=DAILY;BYMONTH=3\r\n",
+            "UID:f\r\nDTSTART:20260301T090000Z\r\nRRULE:FREQ=DAILY;BYDAY=MO\r\n",
+            "UID:g\r\nDTSTART:20260301T090000Z\r\nRRULE:FREQ=DAILY;INTERVAL=0\r\n",
+            "UID:h\r\nDTSTART:20260301T090000Z\r\nRRULE:COUNT=2\r\n",
+            "UID:i\r\nDTSTART:20260301T090000Z\r\nRDATE:20260302T090000Z\r\n",
+            "UID:j\r\nDTSTART;TZID=Europe/Amsterdam:20260329T023000\r\n",
+        ];
+        assert_eq!(
+            errors(&vevents),
+            vec![
+                "VEVENT without UID",
+                "VEVENT without DTSTART",
+                "Invalid date or time 2026-03-01",
+                "Unknown time zone Mars/Olympus_Mons",
+                "Unsupported RRULE frequency HOURLY",
+                "Unsupported RRULE part BYMONTH",
+                "RRULE BYDAY is only supported with FREQ=WEEKLY",
+                "Invalid RRULE interval 0",
+                "RRULE without FREQ",
+                "RDATE is not supported",
+                "DTSTART does not exist in its time zone",
+            ]
+        );
+    }
+
+    #[test]
+    fn valid_and_invalid() {
+        let vevents = ["UID:a\r\n", "UID:b\r\nDTSTART:20260301T090000Z\r\n"];
+        let occurrences = parse(&vevents);
+        assert_eq!(occurrences.len(), 2);
+        assert_eq!(
+            occurrences[0].as_ref().err().and_then(Invalid::uid),
+            Some("a")
+        );
+        assert_eq!(occurrences[1].as_ref().map(Occurrence::key).ok(), Some("b"));
+    }
+
+    #[test]
+    fn folded_and_escaped_lines() {
+        let line_lengths_ok = |ics: &str| ics.split("\r\n").all(|line| line.len() <= 75);
+        let mut folded = String::new();
+        line(
+            &mut folded,
+            &format!("SUMMARY:{}", escape(&"a, b;\n".repeat(20))),
+        );
+        assert!(line_lengths_ok(&folded));
+
+        let vevent = format!("UID:a\r\nDTSTART:20260301T090000Z\r\n{}", folded);
+        let occurrences = parse(&[&vevent]);
+        let occurrence = occurrences[0].as_ref().map_err(|e| &e.error).unwrap();
+        assert_eq!(occurrence.value["summary"], "a, b;\n".repeat(20));
+    }
+}
@@ -1,9 +1,13 @@
 use env_logger::fmt::{Color, Formatter};
+use log::kv::{self, Key, Value, VisitSource};
 use log::{Level, LevelFilter, Record};
+use serde_json::{Map, Value as Json};
 use std::io;
 use std::io::Write;
 use std::str::FromStr;
 
+use crate::trace;
+
 #[derive(Debug)]
 pub struct Verbosity(u8);
 
@@ -45,20 +49,39 @@ impl TryFrom<u8> for Verbosity {
     }
 }
 
-pub fn setup_logging(verb: &Verbosity) {
-    match std::env::var("RUST_LOG") {
-        Ok(_) => log_by_env_var(),
-        Err(_) => log_by_cmd_arg(verb),
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unsupported log format '{}'", s)),
+        }
     }
 }
 
-fn log_by_env_var() {
-    env_logger::Builder::from_default_env()
-        .format(formatter)
-        .init()
+pub fn setup_logging(verb: &Verbosity, format: LogFormat) {
+    let mut builder = match std::env::var("RUST_LOG") {
+        Ok(_) => env_logger::Builder::from_default_env(),
+        Err(_) => log_by_cmd_arg(verb),
+    };
+
+    match format {
+        LogFormat::Text => builder.format(formatter),
+        LogFormat::Json => builder.format(json_formatter),
+    };
+
+    builder.init()
 }
 
-fn log_by_cmd_arg(verb: &Verbosity) {
+fn log_by_cmd_arg(verb: &Verbosity) -> env_logger::Builder {
     let filter: LevelFilter = match verb.level() {
         0 => LevelFilter::Off,
         1 => LevelFilter::Error,
@@ -69,25 +92,92 @@ fn log_by_cmd_arg(verb: &Verbosity) {
         _ => panic!("Invalid verbosity level: {}", verb.level()),
     };
 
-    env_logger::builder()
-        .format(formatter)
-        .filter_level(filter)
-        .init()
+    let mut builder = env_logger::builder();
+    builder.filter_level(filter);
+    builder
 }
 
 fn formatter(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let request: String = match trace::request_id() {
+        Some(id) => format!(" [{}]", id),
+        None => String::new(),
+    };
+
     match record.level() {
-        Level::Info => writeln!(buf, "{}", record.args()),
+        Level::Info => writeln!(buf, "{}{}", record.args(), request),
         Level::Warn => {
             let mut style = buf.style();
             style.set_color(Color::Yellow);
-            writeln!(buf, "{}: {}", style.value(record.level()), record.args())
+            writeln!(
+                buf,
+                "{}: {}{}",
+                style.value(record.level()),
+                record.args(),
+                request
+            )
         }
         Level::Error => {
             let mut style = buf.style();
             style.set_color(Color::Red);
-            writeln!(buf, "{}: {}", style.value(record.level()), record.args())
+            writeln!(
+                buf,
+                "{}: {}{}",
+                style.value(record.level()),
+                record.args(),
+                request
+            )
         }
-        _ => writeln!(buf, "{}: {}", record.level(), record.args()),
+        _ => writeln!(buf, "{}: {}{}", record.level(), record.args(), request),
+    }
+}
+
+/// Names of the properties of every JSON log line, which fields of a record cannot replace
+const JSON_PROPERTIES: [&str; 5] = ["timestamp", "level", "target", "message", "request_id"];
+
+/// Write a record as a single line of JSON, with the structured fields of the record, such as
+/// the namespace or id of an event, as properties next to the message
+fn json_formatter(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let mut line: Map<String, Json> = Map::new();
+    line.insert(
+        "timestamp".to_string(),
+        Json::String(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true)),
+    );
+    line.insert(
+        "level".to_string(),
+        Json::String(record.level().to_string()),
+    );
+    line.insert(
+        "target".to_string(),
+        Json::String(record.target().to_string()),
+    );
+    line.insert(
+        "message".to_string(),
+        Json::String(record.args().to_string()),
+    );
+    if let Some(id) = trace::request_id() {
+        line.insert("request_id".to_string(), Json::String(id));
+    }
+
+    let mut fields = Fields(&mut line);
+    record
+        .key_values()
+        .visit(&mut fields)
+        .map_err(io::Error::other)?;
+
+    serde_json::to_writer(&mut *buf, &line)?;
+    writeln!(buf)
+}
+
+struct Fields<'a>(&'a mut Map<String, Json>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        if JSON_PROPERTIES.contains(&key.as_str()) {
+            return Ok(());
+        }
+
+        let value: Json = serde_json::to_value(&value).unwrap_or_else(|_| value.to_string().into());
+        self.0.insert(key.to_string(), value);
+        Ok(())
     }
 }
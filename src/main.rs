@@ -1,53 +1,225 @@
 use std::sync::Arc;
 
 use clap::Parser;
-use tokio::sync::Mutex;
-use tokio_postgres::NoTls;
+use deadpool_postgres::Pool;
+use log::{error, info};
+use postgres_native_tls::MakeTlsConnector;
+use tide_rustls::TlsListener;
+use tide_websockets::WebSocket;
+use tokio::signal::unix::{signal, SignalKind};
 
-use crate::config::Config;
+use crate::config::{Command, Config};
 use crate::db::event::EventRepoPgsql;
-use crate::http::event::{schedule_event, search_events, settle_and_next, settle_event};
+use crate::feed::Feed;
+use crate::health::Readiness;
+use crate::http::event::{
+    calendar, event_history, event_lineage, import_calendar, overdue_events, poll_events,
+    schedule_batch, schedule_event, search_events, series_events, settle_and_next,
+    settle_and_next_batch, settle_batch, settle_event,
+};
+use crate::http::health::Probes;
+use crate::http::retention::{
+    delete_retention_policy, get_retention_policy, list_retention_policies, set_retention_policy,
+};
+use crate::http::stream::{changes, due_events};
 use crate::logger::setup_logging;
+use crate::metrics::MeteredRoutes;
+use crate::poll::Poller;
+use crate::tls::Certificates;
+use crate::trace::Traced;
 
+mod archive;
 mod config;
+mod csv;
 mod db;
 mod event;
+mod export;
+mod feed;
+mod grpc;
+mod health;
 mod http;
+mod ics;
 mod logger;
+mod metrics;
+mod poll;
+mod retention;
 mod search;
+mod tls;
+mod trace;
 mod webhook;
+mod ws;
 
 #[tokio::main]
 async fn main() {
     let cfg: Config = Config::parse();
-    println!("{}", cfg.db_url());
-    setup_logging(&cfg.verbosity());
+    setup_logging(&cfg.verbosity(), cfg.log_format());
+    trace::setup_tracing(cfg.trace_export()).unwrap();
 
-    let (client, con0) = tokio_postgres::connect(cfg.db_url(), NoTls).await.unwrap();
-    let (client_trx, con1) = tokio_postgres::connect(cfg.db_url(), NoTls).await.unwrap();
+    let db_tls: MakeTlsConnector = db::pool::tls(cfg.db_ca_cert()).unwrap();
+    let pool: Pool = db::pool::create(
+        cfg.db_url(),
+        cfg.db_pool_size(),
+        cfg.db_pool_timeout(),
+        db_tls.clone(),
+    )
+    .unwrap();
 
-    tokio::spawn(async move {
-        if let Err(e) = con0.await {
-            eprintln!("connection error: {}", e);
+    // The schema is created on a connection of its own, since pooled connections prepare
+    // statements that depend on it as soon as they are made
+    let (client, connection) = tokio_postgres::connect(cfg.db_url(), db_tls.clone())
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+    EventRepoPgsql::init(&client).await.unwrap();
+    drop(client);
+
+    let repo = EventRepoPgsql::new(pool).await.unwrap();
+
+    match cfg.command() {
+        Some(Command::Purge(args)) => retention::purge_cmd(&repo, args).await.unwrap(),
+        Some(Command::Archive(args)) => archive::archive_cmd(&repo, args).await.unwrap(),
+        Some(Command::Restore(args)) => archive::restore_cmd(&repo, args).await.unwrap(),
+        Some(Command::Export(args)) => export::export_cmd(&repo, args).await.unwrap(),
+        Some(Command::Import(args)) => export::import_cmd(&repo, args).await.unwrap(),
+        Some(Command::ImportIcs(args)) => ics::import_cmd(&repo, args).await.unwrap(),
+        None => tokio::select! {
+            _ = serve(&cfg, repo, db_tls) => (),
+            _ = shutdown_signal() => info!("Shutting down"),
+        },
+    }
+
+    trace::shutdown_tracing();
+}
+
+async fn serve(cfg: &Config, repo: EventRepoPgsql, db_tls: MakeTlsConnector) {
+    if let Some(interval) = cfg.reap_interval() {
+        tokio::spawn(retention::reap(
+            repo.clone(),
+            interval,
+            cfg.reap_batch_size(),
+        ));
+    }
+
+    if let Some(interval) = cfg.metrics_interval() {
+        tokio::spawn(metrics::sample(repo.clone(), interval));
+    }
+
+    let readiness = Readiness::new(&[
+        health::DATABASE,
+        health::FEED,
+        health::SCHEMA,
+        health::STATEMENTS,
+    ]);
+    tokio::spawn(health::watch_database(repo.clone(), readiness.clone()));
+
+    let feed = Feed::new();
+    tokio::spawn(feed::listen(
+        cfg.db_url().to_string(),
+        db_tls,
+        feed.clone(),
+        readiness.clone(),
+    ));
+
+    let poller = Poller::new(repo.clone(), feed.clone());
+
+    let tls: Option<Arc<Certificates>> = cfg
+        .tls()
+        .map(|(cert, key)| Certificates::load(cert, key).unwrap());
+    if let Some(certificates) = &tls {
+        tokio::spawn(tls::reload_on_hangup(certificates.clone()));
+    }
+
+    if let Some(addr) = cfg.grpc_addr() {
+        let service = grpc::TimetableService::new(repo.clone(), feed.clone(), poller.clone());
+        tokio::spawn(grpc::serve(addr, service, tls.clone()));
+    }
+
+    let mut app = tide::with_state(repo);
+    app.with(Traced);
+    app.with(Probes::new(readiness));
+    app.with(tide::utils::Before(
+        move |mut req: tide::Request<EventRepoPgsql>| {
+            req.set_ext(feed.clone());
+            req.set_ext(poller.clone());
+            async move { req }
+        },
+    ));
+    app.at("/metrics").get(metrics::scrape);
+    app.metered("/v1/schedule").put(schedule_event);
+    app.metered("/v1/schedule/settle").put(settle_event);
+    app.metered("/v1/schedule/next").put(settle_and_next);
+    app.metered("/v1/schedule/search").post(search_events);
+    app.metered("/v1/schedule/batch").put(schedule_batch);
+    app.metered("/v1/schedule/settle/batch").put(settle_batch);
+    app.metered("/v1/schedule/next/batch")
+        .put(settle_and_next_batch);
+    app.metered("/v1/schedule/overdue").get(overdue_events);
+    app.metered("/v1/schedule/poll").post(poll_events);
+    app.metered("/v1/namespaces/:namespace/events/:id/history")
+        .get(event_history);
+    app.metered("/v1/namespaces/:namespace/events/:id/lineage")
+        .get(event_lineage);
+    app.metered("/v1/namespaces/:namespace/series/:id")
+        .get(series_events);
+    app.metered("/v1/namespaces/:namespace/calendar.ics")
+        .get(calendar);
+    app.metered("/v1/namespaces/:namespace/calendar.ics/import")
+        .post(import_calendar);
+    app.metered("/v1/namespaces/:namespace/stream")
+        .get(due_events);
+    app.metered("/v1/changes").get(changes);
+    app.metered("/v1/subscriptions")
+        .get(WebSocket::new(ws::subscriptions));
+    app.metered("/v1/retention").get(list_retention_policies);
+    app.metered("/v1/namespaces/:namespace/retention")
+        .get(get_retention_policy)
+        .put(set_retention_policy)
+        .delete(delete_retention_policy);
+
+    match (cfg.unix_socket(), tls) {
+        (Some(path), _) => {
+            remove_stale_socket(path);
+            app.listen(format!("http+unix://{}", path.display()))
+                .await
+                .unwrap()
         }
-    });
+        (None, Some(certificates)) => app
+            .listen(
+                TlsListener::build()
+                    .addrs(cfg.http_addr())
+                    .config(certificates.server_config(&[b"http/1.1"])),
+            )
+            .await
+            .unwrap(),
+        (None, None) => app.listen(cfg.http_addr()).await.unwrap(),
+    }
+}
 
-    tokio::spawn(async move {
-        if let Err(e) = con1.await {
-            eprintln!("connection error: {}", e);
+/// Wait until the process is asked to stop, by ctrl-c or SIGTERM, so that spans that have not
+/// been exported yet can be flushed before exiting
+async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            error!("Unable to listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
         }
-    });
+    };
 
-    let client = Arc::new(client);
-    let client_trx = Arc::new(Mutex::new(client_trx));
-    let mut repo = EventRepoPgsql::new(client, client_trx).await.unwrap();
-    repo.init().await.unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = terminate.recv() => (),
+    }
+}
 
-    let mut app = tide::with_state(repo);
-    app.at("/v1/schedule").put(schedule_event);
-    app.at("/v1/schedule/settle").put(settle_event);
-    app.at("/v1/schedule/next").put(settle_and_next);
-    app.at("/v1/schedule/search").post(search_events);
-    let bind: String = format!("127.0.0.1:{}", 3000);
-    app.listen(&bind).await.unwrap();
+/// Remove a socket left behind by a previous run, which would otherwise prevent listening on it
+fn remove_stale_socket(path: &std::path::Path) {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
 }
@@ -0,0 +1,186 @@
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use log::debug;
+use prometheus::{
+    register_gauge_vec, register_histogram, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge_vec, Encoder, GaugeVec, Histogram, HistogramVec, IntCounterVec, IntGaugeVec,
+    TextEncoder,
+};
+use tide::{Middleware, Next, Request, Route, Server};
+
+use crate::db::event::{EventRepoPgsql, RepoErr};
+use crate::event::Overdue;
+
+lazy_static! {
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "timetable_http_requests_total",
+        "HTTP requests by route, method and response status",
+        &["route", "method", "status"]
+    )
+    .unwrap();
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "timetable_http_request_duration_seconds",
+        "Time until the response to an HTTP request is ready, by route and method",
+        &["route", "method"]
+    )
+    .unwrap();
+    static ref QUERY_DURATION: HistogramVec = register_histogram_vec!(
+        "timetable_repo_query_duration_seconds",
+        "Time taken by repository queries, including waiting for a connection",
+        &["query"]
+    )
+    .unwrap();
+    static ref REPO_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "timetable_repo_errors_total",
+        "Errors returned by repository queries, by variant",
+        &["query", "variant"]
+    )
+    .unwrap();
+    static ref POOL_WAIT: Histogram = register_histogram!(
+        "timetable_db_pool_wait_seconds",
+        "Time spent waiting for a connection from the database pool"
+    )
+    .unwrap();
+    static ref EVENTS: IntGaugeVec = register_int_gauge_vec!(
+        "timetable_events",
+        "Events by namespace and state, as of the latest sample",
+        &["namespace", "state"]
+    )
+    .unwrap();
+    static ref OVERDUE_EVENTS: IntGaugeVec = register_int_gauge_vec!(
+        "timetable_overdue_events",
+        "Scheduled events that are past due by namespace, as of the latest sample",
+        &["namespace"]
+    )
+    .unwrap();
+    static ref OVERDUE_LAG: GaugeVec = register_gauge_vec!(
+        "timetable_overdue_max_lag_seconds",
+        "How long the oldest overdue event of each namespace is past due, as of the latest sample",
+        &["namespace"]
+    )
+    .unwrap();
+}
+
+/// Time a repository query, counting the error it fails with, if any
+pub async fn time_query<T, F>(query: &'static str, future: F) -> Result<T, RepoErr>
+where
+    F: Future<Output = Result<T, RepoErr>>,
+{
+    let start = Instant::now();
+    let res: Result<T, RepoErr> = future.await;
+    QUERY_DURATION
+        .with_label_values(&[query])
+        .observe(start.elapsed().as_secs_f64());
+
+    if let Err(e) = &res {
+        REPO_ERRORS.with_label_values(&[query, e.variant()]).inc();
+    }
+
+    res
+}
+
+pub fn observe_pool_wait(wait: Duration) {
+    POOL_WAIT.observe(wait.as_secs_f64());
+}
+
+/// Counts and times the requests of a single route. Since the path of a request only matches a
+/// route once it has been routed, this is added to each route rather than to the whole server,
+/// so that requests are labelled by route instead of by path.
+struct Metered {
+    route: &'static str,
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Metered {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let method: String = req.method().to_string();
+        let start = Instant::now();
+        let res = next.run(req).await;
+
+        HTTP_REQUEST_DURATION
+            .with_label_values(&[self.route, &method])
+            .observe(start.elapsed().as_secs_f64());
+        HTTP_REQUESTS
+            .with_label_values(&[self.route, &method, &res.status().to_string()])
+            .inc();
+
+        Ok(res)
+    }
+}
+
+/// Routes whose requests are counted and timed
+pub trait MeteredRoutes<State> {
+    fn metered(&mut self, path: &'static str) -> Route<'_, State>;
+}
+
+impl<State: Clone + Send + Sync + 'static> MeteredRoutes<State> for Server<State> {
+    fn metered(&mut self, path: &'static str) -> Route<'_, State> {
+        let mut route = self.at(path);
+        route.with(Metered { route: path });
+        route
+    }
+}
+
+/// All metrics in the Prometheus text format
+pub async fn scrape<State>(_req: Request<State>) -> tide::Result {
+    let mut body: Vec<u8> = Vec::new();
+    let encoder = TextEncoder::new();
+    encoder.encode(&prometheus::gather(), &mut body)?;
+
+    let res = tide::Response::builder(200)
+        .content_type(encoder.format_type())
+        .body(body)
+        .build();
+
+    Ok(res)
+}
+
+/// Count events by state and overdue events at each interval. Namespaces that no longer have
+/// any events are dropped from the gauges, while namespaces without overdue events report none.
+/// The previous sample is kept if the database cannot be reached, which is reported by the
+/// readiness checks.
+pub async fn sample(repo: EventRepoPgsql, interval: Duration) {
+    let mut namespaces: BTreeSet<String> = BTreeSet::new();
+
+    loop {
+        match repo.count_by_state().await {
+            Ok(counts) => {
+                EVENTS.reset();
+                namespaces.clear();
+                for (namespace, state, count) in counts {
+                    EVENTS.with_label_values(&[&namespace, &state]).set(count);
+                    namespaces.insert(namespace);
+                }
+            }
+            Err(e) => debug!("Unable to count events by state: {:?}", e),
+        }
+
+        match repo.overdue().await {
+            Ok(overdue) => {
+                OVERDUE_EVENTS.reset();
+                OVERDUE_LAG.reset();
+                for namespace in &namespaces {
+                    OVERDUE_EVENTS.with_label_values(&[namespace]).set(0);
+                    OVERDUE_LAG.with_label_values(&[namespace]).set(0.0);
+                }
+                overdue.iter().for_each(set_overdue);
+            }
+            Err(e) => debug!("Unable to count overdue events: {:?}", e),
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+fn set_overdue(overdue: &Overdue) {
+    let labels = [overdue.namespace()];
+    OVERDUE_EVENTS
+        .with_label_values(&labels)
+        .set(overdue.overdue());
+    OVERDUE_LAG
+        .with_label_values(&labels)
+        .set(overdue.max_lag_ms() as f64 / 1000.0);
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future;
+use futures::pin_mut;
+use log::{debug, error};
+use tokio::sync::{broadcast, watch};
+
+use crate::db::event::EventRepoPgsql;
+use crate::event::Event;
+use crate::feed::{Feed, Notice};
+
+/// Maximum number of due events kept for waiters in each namespace
+const MAX_DUE: i64 = 100;
+
+/// Longest time to wait before looking for due events again, in case a change is missed
+const MAX_WAIT: Duration = Duration::from_secs(5);
+
+/// How soon to look again while there are due events, so that settled events stop being handed
+/// out even if the change notification is missed
+const DUE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Due events in a namespace, or `None` until the first check has been done
+pub type Due = Option<Arc<Vec<Event>>>;
+
+/// Lets any number of clients wait for events in a namespace to become due. There is a single
+/// watcher per namespace that has waiters, which looks for due events when the next one is
+/// expected to become due or when notified of a change, and shares the result with all waiters.
+#[derive(Clone)]
+pub struct Poller {
+    repo: EventRepoPgsql,
+    feed: Feed,
+    watchers: Arc<Mutex<HashMap<String, Arc<watch::Sender<Due>>>>>,
+}
+
+impl Poller {
+    pub fn new(repo: EventRepoPgsql, feed: Feed) -> Poller {
+        Poller {
+            repo,
+            feed,
+            watchers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Wait until there is at least one scheduled event in the namespace that is due, returning at
+    /// most `limit` due events, earliest first. Returns an empty list if no event became due
+    /// before the timeout.
+    pub async fn wait(&self, namespace: &str, timeout: Duration, limit: usize) -> Vec<Event> {
+        let mut due: watch::Receiver<Due> = self.subscribe(namespace);
+        let deadline = std::time::Instant::now() + timeout;
+
+        loop {
+            if let Some(events) = &*due.borrow() {
+                if !events.is_empty() {
+                    return events.iter().take(limit).cloned().collect();
+                }
+            }
+
+            let remaining: Duration = deadline.saturating_duration_since(std::time::Instant::now());
+            match async_std::future::timeout(remaining, due.changed()).await {
+                Ok(Ok(())) => continue,
+                Ok(Err(_)) | Err(_) => return Vec::new(),
+            }
+        }
+    }
+
+    /// Receive the due events in the namespace each time they have been looked for, for as long as
+    /// the receiver is kept.
+    pub fn subscribe(&self, namespace: &str) -> watch::Receiver<Due> {
+        let mut watchers = self.watchers.lock().unwrap();
+        if let Some(sender) = watchers.get(namespace) {
+            return sender.subscribe();
+        }
+
+        let (sender, receiver) = watch::channel(None);
+        let sender = Arc::new(sender);
+        watchers.insert(namespace.to_string(), sender.clone());
+        async_std::task::spawn(self.clone().watch(namespace.to_string(), sender));
+
+        receiver
+    }
+
+    /// Look for due events in the namespace for as long as there are waiters
+    async fn watch(self, namespace: String, sender: Arc<watch::Sender<Due>>) {
+        debug!(namespace = namespace.as_str(); "Watching for due events in namespace {}", namespace);
+        let mut notices: broadcast::Receiver<Notice> = self.feed.subscribe();
+
+        loop {
+            {
+                let mut watchers = self.watchers.lock().unwrap();
+                if sender.receiver_count() == 0 {
+                    watchers.remove(&namespace);
+                    debug!(namespace = namespace.as_str(); "No more waiters in namespace {}", namespace);
+                    return;
+                }
+            }
+
+            let now: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
+            let wait: Duration = match self.check(&namespace, now).await {
+                Ok((due, next)) => {
+                    let wait: Duration = match (due.is_empty(), next) {
+                        (false, _) => DUE_INTERVAL,
+                        (true, Some(next)) => (next - now).to_std().unwrap_or_default(),
+                        (true, None) => MAX_WAIT,
+                    };
+                    sender.send_replace(Some(Arc::new(due)));
+                    wait
+                }
+                Err(e) => {
+                    error!(namespace = namespace.as_str(); "Error looking for due events in {}, {}", namespace, e);
+                    MAX_WAIT
+                }
+            };
+
+            let timer = async_std::task::sleep(wait.min(MAX_WAIT));
+            let changed = next_due_changed(&mut notices, &namespace);
+            let closed = sender.closed();
+            pin_mut!(timer, changed, closed);
+            future::select(timer, future::select(changed, closed)).await;
+        }
+    }
+
+    /// Due events in the namespace, and when the next event after those is due
+    async fn check(
+        &self,
+        namespace: &str,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<(Vec<Event>, Option<chrono::DateTime<chrono::Utc>>), String> {
+        let since = chrono::DateTime::<chrono::Utc>::from(std::time::UNIX_EPOCH);
+        let due: Vec<Event> = self
+            .repo
+            .due(namespace, (since, uuid::Uuid::nil()), now, MAX_DUE)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let next = self
+            .repo
+            .next_due(namespace, now)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok((due, next))
+    }
+}
+
+/// Wait for a notice that the next due time of the namespace may have changed
+async fn next_due_changed(notices: &mut broadcast::Receiver<Notice>, namespace: &str) {
+    loop {
+        match notices.recv().await {
+            Ok(Notice::NextDue { namespace: ns }) if ns == namespace => return,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(_)) => return,
+            Err(broadcast::error::RecvError::Closed) => future::pending::<()>().await,
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+use tokio_postgres::Row;
+
+use crate::config::PurgeArgs;
+use crate::db::event::{EventRepoPgsql, RepoErr};
+
+/// Retention of finished (completed or disabled) events in a namespace. Events are purged when
+/// they were scheduled more than `max_age_seconds` ago, or when there are more than `max_count`
+/// newer finished events for the same key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RetentionPolicy {
+    namespace: String,
+    #[serde(rename = "maxAgeSeconds")]
+    max_age_seconds: Option<i64>,
+    #[serde(rename = "maxCount")]
+    max_count: Option<i64>,
+}
+
+impl RetentionPolicy {
+    pub fn new(
+        namespace: String,
+        max_age_seconds: Option<i64>,
+        max_count: Option<i64>,
+    ) -> RetentionPolicy {
+        RetentionPolicy {
+            namespace,
+            max_age_seconds,
+            max_count,
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn max_age_seconds(&self) -> Option<i64> {
+        self.max_age_seconds
+    }
+
+    pub fn max_count(&self) -> Option<i64> {
+        self.max_count
+    }
+}
+
+impl TryFrom<&Row> for RetentionPolicy {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        let policy = RetentionPolicy {
+            namespace: value.try_get(0)?,
+            max_age_seconds: value.try_get(1)?,
+            max_count: value.try_get(2)?,
+        };
+
+        Ok(policy)
+    }
+}
+
+/// Delete all events that have expired according to the retention policies, one batch at a time
+/// so that no single statement holds locks on a large number of rows. Returns the number of
+/// deleted events per namespace.
+pub async fn purge(
+    repo: &EventRepoPgsql,
+    namespace: Option<&str>,
+    batch_size: i64,
+) -> Result<BTreeMap<String, i64>, RepoErr> {
+    let mut purged: BTreeMap<String, i64> = BTreeMap::new();
+
+    loop {
+        let namespaces: Vec<String> = repo.purge_expired(namespace, batch_size).await?;
+        let deleted: usize = namespaces.len();
+
+        for namespace in namespaces {
+            *purged.entry(namespace).or_insert(0) += 1;
+        }
+
+        if (deleted as i64) < batch_size {
+            return Ok(purged);
+        }
+    }
+}
+
+/// Periodically purge expired events, until the process exits.
+pub async fn reap(repo: EventRepoPgsql, interval: std::time::Duration, batch_size: i64) {
+    loop {
+        tokio::time::sleep(interval).await;
+        match purge(&repo, None, batch_size).await {
+            Ok(purged) => {
+                for (namespace, count) in purged {
+                    info!(namespace = namespace.as_str(); "Purged {} events from namespace {}", count, namespace);
+                }
+            }
+            Err(e) => error!("Unable to purge expired events: {:?}", e),
+        }
+    }
+}
+
+/// Purge expired events from the command line, or only list how many events would be purged if
+/// this is a dry run.
+pub async fn purge_cmd(repo: &EventRepoPgsql, args: &PurgeArgs) -> Result<(), RepoErr> {
+    let namespace: Option<&str> = args.namespace();
+
+    let (verb, counts) = match args.dry_run() {
+        true => ("Would purge", repo.expired(namespace).await?),
+        false => ("Purged", purge(repo, namespace, args.batch_size()).await?),
+    };
+
+    if counts.is_empty() {
+        println!("No expired events");
+    }
+
+    for (namespace, count) in counts {
+        println!("{} {} events from namespace {}", verb, count, namespace);
+    }
+
+    Ok(())
+}
@@ -1,9 +1,6 @@
-use std::{convert::Infallible, ops::RangeInclusive, sync::Arc, vec};
+use std::{convert::Infallible, vec};
 
-use chrono::TimeZone;
-use postgres_types::ToSql;
 use serde_derive::Deserialize;
-use tokio_postgres::{GenericClient, Row};
 
 use crate::{
     db::event::EventRepoPgsql,
@@ -24,6 +21,25 @@ pub struct SearchQuery {
 }
 
 impl SearchQuery {
+    pub fn new(
+        namespace: String,
+        key: Option<String>,
+        state: Option<Vec<State>>,
+        limit: Option<u32>,
+        scheduled_at_min: Option<chrono::DateTime<chrono::Utc>>,
+        scheduled_at_max: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> SearchQuery {
+        SearchQuery {
+            namespace,
+            key,
+            state,
+            order: None,
+            limit,
+            scheduled_at_min,
+            scheduled_at_max,
+        }
+    }
+
     pub fn namespace(&self) -> &str {
         &self.namespace
     }
@@ -47,6 +63,11 @@ impl SearchQuery {
         self.limit.unwrap_or(100) as i64
     }
 
+    /// The limit if one was given, so that all matching events are included otherwise.
+    pub fn explicit_limit(&self) -> Option<i64> {
+        self.limit.map(|limit| limit as i64)
+    }
+
     pub fn scheduled_at(
         &self,
     ) -> (
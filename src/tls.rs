@@ -0,0 +1,117 @@
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use log::{error, info};
+use rustls::sign::{self, CertifiedKey};
+use rustls::{
+    Certificate, ClientHello, NoClientAuth, PrivateKey, ResolvesServerCert, ServerConfig,
+};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// The certificate chain and private key that TLS connections are served with. They are read
+/// from PEM files, and can be read again to pick up a renewed certificate without restarting.
+pub struct Certificates {
+    cert: PathBuf,
+    key: PathBuf,
+    current: RwLock<CertifiedKey>,
+}
+
+impl Certificates {
+    pub fn load(cert: &Path, key: &Path) -> Result<Arc<Certificates>, String> {
+        let current: CertifiedKey = read(cert, key)?;
+        info!("Loaded TLS certificate from {}", cert.display());
+
+        Ok(Arc::new(Certificates {
+            cert: cert.to_path_buf(),
+            key: key.to_path_buf(),
+            current: RwLock::new(current),
+        }))
+    }
+
+    /// Read the certificate and key files again. The current certificate is kept if they cannot
+    /// be read.
+    pub fn reload(&self) -> Result<(), String> {
+        let reloaded: CertifiedKey = read(&self.cert, &self.key)?;
+        *self.current.write().unwrap() = reloaded;
+        Ok(())
+    }
+
+    /// Configuration for serving TLS with the current certificate, negotiating one of the given
+    /// application protocols
+    pub fn server_config(self: &Arc<Self>, protocols: &[&[u8]]) -> ServerConfig {
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.cert_resolver = self.clone();
+        config.set_protocols(&protocols.iter().map(|p| p.to_vec()).collect::<Vec<_>>());
+        config
+    }
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Reload the certificates each time the process receives SIGHUP
+pub async fn reload_on_hangup(certificates: Arc<Certificates>) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            error!(
+                "Unable to listen for SIGHUP, certificates will not be reloaded: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        match certificates.reload() {
+            Ok(()) => info!(
+                "Reloaded TLS certificate from {}",
+                certificates.cert.display()
+            ),
+            Err(e) => error!(
+                "Unable to reload TLS certificate, keeping the current one: {}",
+                e
+            ),
+        }
+    }
+}
+
+fn read(cert: &Path, key: &Path) -> Result<CertifiedKey, String> {
+    let chain: Vec<Certificate> = read_pem(cert)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+
+    if chain.is_empty() {
+        return Err(format!("No certificate found in {}", cert.display()));
+    }
+
+    let private_key: PrivateKey = read_pem(key)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No private key found in {}", key.display()))?;
+
+    let signing_key = sign::any_supported_type(&private_key)
+        .map_err(|_| format!("Unsupported private key in {}", key.display()))?;
+
+    Ok(CertifiedKey::new(chain, Arc::new(signing_key)))
+}
+
+fn read_pem(path: &Path) -> Result<Vec<rustls_pemfile::Item>, String> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("Unable to open {}: {}", path.display(), e))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))
+}
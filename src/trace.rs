@@ -0,0 +1,364 @@
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::trace::{SpanKind, StatusCode, TraceError, TracerProvider};
+use opentelemetry::{global, Array, KeyValue, Value};
+use opentelemetry_otlp::WithExportConfig;
+use serde_json::{json, Value as Json};
+use tide::{Middleware, Next, Request};
+use tonic::codegen::{http, Service};
+use tonic::transport::NamedService;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{info_span, Instrument, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Registry;
+
+/// Header a request id is read from and echoed in
+const REQUEST_ID: &str = "X-Request-Id";
+
+/// Metadata a request id is read from and echoed in by the gRPC API
+const GRPC_REQUEST_ID: &str = "x-request-id";
+
+/// Longest request id that is accepted from a client, rather than replaced by a generated one
+const MAX_REQUEST_ID_LEN: usize = 200;
+
+/// Where spans are exported to, if anywhere
+pub enum Export<'a> {
+    /// An OpenTelemetry collector, over OTLP/gRPC
+    Collector(&'a str),
+    /// A local file, with one OTLP/JSON export request per line
+    File(&'a Path),
+}
+
+/// Track the spans of requests and the repository calls made for them, so that log lines can
+/// include the id of the request they were written for, and export them if configured to.
+pub fn setup_tracing(export: Option<Export>) -> Result<(), TraceError> {
+    let resource = Resource::new([KeyValue::new("service.name", "timetable")]);
+    let config = trace::config().with_resource(resource);
+
+    let tracer = match export {
+        Some(Export::Collector(endpoint)) => {
+            let exporter = opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint);
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(exporter)
+                .with_trace_config(config)
+                .install_batch(opentelemetry::runtime::Tokio)?;
+            Some(tracer)
+        }
+        Some(Export::File(path)) => {
+            let file = File::options()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| {
+                    TraceError::from(format!("Unable to open {}: {}", path.display(), e))
+                })?;
+            let provider = trace::TracerProvider::builder()
+                .with_config(config)
+                .with_batch_exporter(FileExporter::new(file), opentelemetry::runtime::Tokio)
+                .build();
+            let tracer =
+                provider.versioned_tracer("timetable", Some(env!("CARGO_PKG_VERSION")), None);
+            global::set_tracer_provider(provider);
+            Some(tracer)
+        }
+        None => None,
+    };
+
+    let subscriber = Registry::default()
+        .with(RequestIds)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)));
+
+    tracing::subscriber::set_global_default(subscriber).map_err(|e| TraceError::from(e.to_string()))
+}
+
+/// Export any spans that have not been exported yet
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+/// Id of the request that the current span, or any span it is part of, was started for
+pub fn request_id() -> Option<String> {
+    tracing::dispatcher::get_default(|dispatch| {
+        let registry: &Registry = dispatch.downcast_ref::<Registry>()?;
+        let current: Id = tracing::Span::current().id()?;
+        let span = registry.span(&current)?;
+
+        let id = span
+            .scope()
+            .find_map(|span| span.extensions().get::<RequestId>().map(|id| id.0.clone()));
+        id
+    })
+}
+
+/// Runs each request in a span of its own, identified by the `X-Request-Id` header of the
+/// request, or by a generated id if there is none. The id is echoed in the response.
+pub struct Traced;
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Traced {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let request_id: String = match req.header(REQUEST_ID).map(|values| values.last().as_str()) {
+            Some(id) if valid_request_id(id) => id.to_string(),
+            _ => uuid::Uuid::new_v4().to_string(),
+        };
+
+        let span = info_span!(
+            "request",
+            request_id = request_id.as_str(),
+            http.method = %req.method(),
+            http.target = req.url().path(),
+            http.status_code = tracing::field::Empty,
+            otel.kind = "server",
+        );
+
+        let mut res = next.run(req).instrument(span.clone()).await;
+        span.record("http.status_code", &u16::from(res.status()));
+        res.insert_header(REQUEST_ID, request_id);
+
+        Ok(res)
+    }
+}
+
+/// Runs each gRPC call in a span of its own, identified by the `x-request-id` metadata of the
+/// call, or by a generated id if there is none. The id is echoed in the response metadata.
+#[derive(Debug, Clone)]
+pub struct GrpcTraced<S> {
+    inner: S,
+}
+
+impl<S> GrpcTraced<S> {
+    pub fn new(inner: S) -> GrpcTraced<S> {
+        GrpcTraced { inner }
+    }
+}
+
+impl<S: NamedService> NamedService for GrpcTraced<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B, ResBody> Service<http::Request<B>> for GrpcTraced<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let request_id: String = match req
+            .headers()
+            .get(GRPC_REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(id) if valid_request_id(id) => id.to_string(),
+            _ => uuid::Uuid::new_v4().to_string(),
+        };
+
+        let span = info_span!(
+            "request",
+            request_id = request_id.as_str(),
+            rpc.system = "grpc",
+            rpc.method = req.uri().path(),
+            otel.kind = "server",
+        );
+
+        let call = span.in_scope(|| self.inner.call(req));
+        Box::pin(async move {
+            let mut res = call.instrument(span).await?;
+            if let Ok(value) = http::HeaderValue::from_str(&request_id) {
+                res.headers_mut().insert(GRPC_REQUEST_ID, value);
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+/// Request ids from clients are only used if they can be logged and echoed safely
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.chars().all(|c| c.is_ascii_graphic())
+}
+
+/// Request id of a span, kept with the span so that it can be looked up for log lines written
+/// within the span or any span within it
+struct RequestId(String);
+
+/// Keeps the request id of each span that has one
+struct RequestIds;
+
+impl<S> Layer<S> for RequestIds
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = RequestIdVisitor(None);
+        attrs.record(&mut visitor);
+
+        if let (Some(request_id), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(RequestId(request_id));
+        }
+    }
+}
+
+struct RequestIdVisitor(Option<String>);
+
+impl Visit for RequestIdVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "request_id" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "request_id" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// Writes spans to a file in the OTLP/JSON encoding, one export request per line, which can be
+/// read by the OTLP JSON file receiver of an OpenTelemetry collector
+#[derive(Debug)]
+struct FileExporter {
+    file: BufWriter<File>,
+}
+
+impl FileExporter {
+    fn new(file: File) -> FileExporter {
+        FileExporter {
+            file: BufWriter::new(file),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl SpanExporter for FileExporter {
+    async fn export(&mut self, batch: Vec<SpanData>) -> ExportResult {
+        let resource: Vec<Json> = match batch.first().and_then(|span| span.resource.as_ref()) {
+            Some(resource) => resource
+                .iter()
+                .map(|(k, v)| attribute(k.as_str(), v))
+                .collect(),
+            None => Vec::new(),
+        };
+        let spans: Vec<Json> = batch.iter().map(span).collect();
+
+        let request = json!({
+            "resourceSpans": [{
+                "resource": { "attributes": resource },
+                "scopeSpans": [{
+                    "scope": { "name": "timetable" },
+                    "spans": spans,
+                }],
+            }],
+        });
+
+        serde_json::to_writer(&mut self.file, &request)
+            .map_err(|e| TraceError::from(e.to_string()))?;
+        writeln!(self.file).map_err(|e| TraceError::from(e.to_string()))?;
+        self.file
+            .flush()
+            .map_err(|e| TraceError::from(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+fn span(span: &SpanData) -> Json {
+    let parent_span_id: String = match span.parent_span_id {
+        opentelemetry::trace::SpanId::INVALID => String::new(),
+        id => id.to_string(),
+    };
+
+    let kind: u8 = match span.span_kind {
+        SpanKind::Internal => 1,
+        SpanKind::Server => 2,
+        SpanKind::Client => 3,
+        SpanKind::Producer => 4,
+        SpanKind::Consumer => 5,
+    };
+
+    let status: u8 = match span.status_code {
+        StatusCode::Unset => 0,
+        StatusCode::Ok => 1,
+        StatusCode::Error => 2,
+    };
+
+    let events: Vec<Json> = span
+        .events
+        .iter()
+        .map(|event| {
+            json!({
+                "timeUnixNano": unix_nanos(event.timestamp),
+                "name": event.name,
+                "attributes": event.attributes.iter().map(|kv| attribute(kv.key.as_str(), &kv.value)).collect::<Vec<Json>>(),
+            })
+        })
+        .collect();
+
+    json!({
+        "traceId": span.span_context.trace_id().to_string(),
+        "spanId": span.span_context.span_id().to_string(),
+        "parentSpanId": parent_span_id,
+        "name": span.name,
+        "kind": kind,
+        "startTimeUnixNano": unix_nanos(span.start_time),
+        "endTimeUnixNano": unix_nanos(span.end_time),
+        "attributes": span.attributes.iter().map(|(k, v)| attribute(k.as_str(), v)).collect::<Vec<Json>>(),
+        "events": events,
+        "status": { "code": status, "message": span.status_message },
+    })
+}
+
+fn attribute(key: &str, value: &Value) -> Json {
+    json!({ "key": key, "value": any_value(value) })
+}
+
+/// Value in the encoding of OTLP/JSON, in which 64 bit integers are strings
+fn any_value(value: &Value) -> Json {
+    match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::I64(i) => json!({ "intValue": i.to_string() }),
+        Value::F64(f) => json!({ "doubleValue": f }),
+        Value::String(s) => json!({ "stringValue": s }),
+        Value::Array(array) => {
+            let values: Vec<Json> = match array {
+                Array::Bool(values) => values.iter().map(|b| json!({ "boolValue": b })).collect(),
+                Array::I64(values) => values
+                    .iter()
+                    .map(|i| json!({ "intValue": i.to_string() }))
+                    .collect(),
+                Array::F64(values) => values.iter().map(|f| json!({ "doubleValue": f })).collect(),
+                Array::String(values) => {
+                    values.iter().map(|s| json!({ "stringValue": s })).collect()
+                }
+            };
+            json!({ "arrayValue": { "values": values } })
+        }
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
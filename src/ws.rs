@@ -0,0 +1,306 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::{self, AbortHandle, Abortable};
+use futures::{pin_mut, StreamExt};
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::json;
+use tide::Request;
+use tide_websockets::{Message, WebSocketConnection};
+use tokio::sync::{broadcast, mpsc, watch};
+
+use crate::db::event::EventRepoPgsql;
+use crate::event::Event;
+use crate::feed::{Feed, Notice, Op};
+use crate::poll::{Due, Poller};
+
+/// Number of messages that may be queued for a client before messages are dropped
+const QUEUE_SIZE: usize = 256;
+
+/// How often the client is pinged when there is nothing else to send. A client that has not sent
+/// anything, including pongs, for two intervals is disconnected.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Longest time sending a single message may take before the client is considered too slow and is
+/// disconnected
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A message from the client, to start or stop receiving messages about events in a namespace
+/// whose keys start with a prefix. Without a prefix, all events in the namespace are included.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    Subscribe {
+        namespace: String,
+        #[serde(alias = "keyPrefix")]
+        key_prefix: Option<String>,
+    },
+    Unsubscribe {
+        namespace: String,
+        #[serde(alias = "keyPrefix")]
+        key_prefix: Option<String>,
+    },
+}
+
+/// Key prefixes subscribed to, per namespace
+#[derive(Default)]
+struct Subscriptions(HashMap<String, HashSet<String>>);
+
+impl Subscriptions {
+    fn matches(&self, namespace: &str, key: &str) -> bool {
+        match self.0.get(namespace) {
+            Some(prefixes) => prefixes
+                .iter()
+                .any(|prefix| key.starts_with(prefix.as_str())),
+            None => false,
+        }
+    }
+}
+
+/// Messages queued for a client. When the client does not keep up and the queue is full, new
+/// messages are dropped and the client is told how many it missed once it has caught up.
+#[derive(Clone)]
+struct Outbox {
+    sender: mpsc::Sender<serde_json::Value>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Outbox {
+    fn push(&self, message: serde_json::Value) {
+        if self.sender.try_send(message).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Serve a WebSocket client, sending it JSON messages about events in the namespaces and key
+/// prefixes it has subscribed to: `insert` when an event is created, `transition` when it changes
+/// state and `due` when it becomes due while still scheduled.
+pub async fn subscriptions(
+    req: Request<EventRepoPgsql>,
+    ws: WebSocketConnection,
+) -> tide::Result<()> {
+    let (feed, poller) = match (req.ext::<Feed>(), req.ext::<Poller>()) {
+        (Some(feed), Some(poller)) => (feed.clone(), poller.clone()),
+        _ => {
+            return Err(tide::Error::from_str(
+                503,
+                "Subscriptions are not available",
+            ))
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+    let outbox = Outbox {
+        sender,
+        dropped: Arc::new(AtomicU64::new(0)),
+    };
+    let subscriptions: Arc<Mutex<Subscriptions>> = Arc::default();
+    let last_seen: Arc<Mutex<Instant>> = Arc::new(Mutex::new(Instant::now()));
+    let due_watchers: Mutex<HashMap<String, AbortHandle>> = Mutex::default();
+
+    let changes = forward_changes(feed.subscribe(), subscriptions.clone(), outbox.clone());
+    let writer = write(
+        ws.clone(),
+        receiver,
+        outbox.dropped.clone(),
+        last_seen.clone(),
+    );
+    let reader = async {
+        let mut ws = ws.clone();
+        while let Some(Ok(message)) = ws.next().await {
+            *last_seen.lock().unwrap() = Instant::now();
+            let text: String = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+
+            let message: ClientMessage = match serde_json::from_str(&text) {
+                Ok(message) => message,
+                Err(e) => {
+                    outbox.push(json!({ "type": "error", "message": e.to_string() }));
+                    continue;
+                }
+            };
+
+            let (subscribe, namespace, prefix) = match message {
+                ClientMessage::Subscribe {
+                    namespace,
+                    key_prefix,
+                } => (true, namespace, key_prefix.unwrap_or_default()),
+                ClientMessage::Unsubscribe {
+                    namespace,
+                    key_prefix,
+                } => (false, namespace, key_prefix.unwrap_or_default()),
+            };
+
+            let watched: bool = {
+                let mut subscriptions = subscriptions.lock().unwrap();
+                let prefixes = subscriptions.0.entry(namespace.clone()).or_default();
+                match subscribe {
+                    true => prefixes.insert(prefix.clone()),
+                    false => prefixes.remove(&prefix),
+                };
+                if prefixes.is_empty() {
+                    subscriptions.0.remove(&namespace);
+                    false
+                } else {
+                    true
+                }
+            };
+
+            let mut due_watchers = due_watchers.lock().unwrap();
+            match (watched, due_watchers.contains_key(&namespace)) {
+                (true, false) => {
+                    let (handle, registration) = AbortHandle::new_pair();
+                    let due = forward_due(
+                        poller.subscribe(&namespace),
+                        namespace.clone(),
+                        subscriptions.clone(),
+                        outbox.clone(),
+                    );
+                    async_std::task::spawn(Abortable::new(due, registration));
+                    due_watchers.insert(namespace.clone(), handle);
+                }
+                (false, true) => {
+                    if let Some(handle) = due_watchers.remove(&namespace) {
+                        handle.abort();
+                    }
+                }
+                _ => (),
+            }
+
+            let kind: &str = match subscribe {
+                true => "subscribed",
+                false => "unsubscribed",
+            };
+            outbox.push(json!({ "type": kind, "namespace": namespace, "keyPrefix": prefix }));
+        }
+    };
+
+    pin_mut!(changes, writer, reader);
+    future::select(reader, future::select(writer, changes)).await;
+
+    for handle in due_watchers.lock().unwrap().values() {
+        handle.abort();
+    }
+
+    debug!("WebSocket client disconnected");
+    Ok(())
+}
+
+/// Send queued messages to the client, and ping it when there is nothing to send. Returns when the
+/// client is gone, too slow or unresponsive.
+async fn write(
+    ws: WebSocketConnection,
+    mut receiver: mpsc::Receiver<serde_json::Value>,
+    dropped: Arc<AtomicU64>,
+    last_seen: Arc<Mutex<Instant>>,
+) {
+    loop {
+        let message: Message =
+            match async_std::future::timeout(HEARTBEAT_INTERVAL, receiver.recv()).await {
+                Ok(Some(message)) => {
+                    let missed: u64 = dropped.swap(0, Ordering::Relaxed);
+                    if missed > 0 {
+                        let lagged = json!({ "type": "lagged", "missed": missed });
+                        if !send(&ws, Message::Text(lagged.to_string())).await {
+                            return;
+                        }
+                    }
+                    Message::Text(message.to_string())
+                }
+                Ok(None) => return,
+                Err(_) => {
+                    if last_seen.lock().unwrap().elapsed() > 2 * HEARTBEAT_INTERVAL {
+                        warn!("Disconnecting unresponsive WebSocket client");
+                        let _ = ws.send(Message::Close(None)).await;
+                        return;
+                    }
+                    Message::Ping(Vec::new())
+                }
+            };
+
+        if !send(&ws, message).await {
+            return;
+        }
+    }
+}
+
+async fn send(ws: &WebSocketConnection, message: Message) -> bool {
+    match async_std::future::timeout(SEND_TIMEOUT, ws.send(message)).await {
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            debug!("Unable to send to WebSocket client, {}", e);
+            false
+        }
+        Err(_) => {
+            warn!("Disconnecting WebSocket client that is too slow to receive messages");
+            false
+        }
+    }
+}
+
+/// Queue inserts and transitions of events that the client has subscribed to
+async fn forward_changes(
+    mut notices: broadcast::Receiver<Notice>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    outbox: Outbox,
+) {
+    loop {
+        let change = match notices.recv().await {
+            Ok(Notice::Change(change)) => change,
+            Ok(Notice::NextDue { .. }) => continue,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                outbox.dropped.fetch_add(missed, Ordering::Relaxed);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return future::pending().await,
+        };
+
+        let subscribed: bool = subscriptions
+            .lock()
+            .unwrap()
+            .matches(change.namespace(), change.key());
+
+        if subscribed {
+            let kind: &str = match change.op() {
+                Op::Insert => "insert",
+                Op::Update => "transition",
+            };
+            outbox.push(json!({ "type": kind, "change": change }));
+        }
+    }
+}
+
+/// Queue each event in the namespace that the client has subscribed to, once, when it is due
+async fn forward_due(
+    mut due: watch::Receiver<Due>,
+    namespace: String,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    outbox: Outbox,
+) {
+    let mut notified: HashSet<uuid::Uuid> = HashSet::new();
+
+    loop {
+        let events: Option<Arc<Vec<Event>>> = due.borrow().clone();
+        if let Some(events) = events {
+            let subscriptions = subscriptions.lock().unwrap();
+            for event in events.iter() {
+                if !notified.contains(&event.id()) && subscriptions.matches(&namespace, event.key())
+                {
+                    outbox.push(json!({ "type": "due", "event": event }));
+                }
+            }
+            notified = events.iter().map(Event::id).collect();
+        }
+
+        if due.changed().await.is_err() {
+            return;
+        }
+    }
+}